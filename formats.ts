@@ -0,0 +1,1045 @@
+import { extname } from 'jsr:@std/path@1.0'
+
+export interface Heading {
+  level: number
+  title: string
+  /** Offset of the start of the heading in the doc content */
+  offset: number
+}
+
+/** Headings are shown to the model Markdown-style regardless of source format */
+export const headingLine = (h: Heading) => `${'#'.repeat(h.level)} ${h.title}`
+
+/** User-controlled knobs for how files get turned into text */
+export interface FormatOptions {
+  /** Keep text outputs of notebook code cells */
+  notebookOutputs: boolean
+  /** Index doc comments from source code files */
+  sourceCode: boolean
+}
+
+/**
+ * A kind of document we know how to index. To support a new file type, add an
+ * entry to `formats` below.
+ */
+export interface Format {
+  /** File extensions handled by this format, without the dot */
+  exts: string[]
+  /** Source code, which is only indexed when `sourceCode` is on */
+  source?: boolean
+  /** Files may start with YAML or TOML frontmatter, which is parsed separately */
+  frontmatter?: boolean
+  /** Turn raw file contents into the text we index. Defaults to as-is. */
+  toText?(raw: string, opts: FormatOptions): string
+  /** Find headings in the text produced by `toText` */
+  headings(text: string): Heading[]
+}
+
+/** Split text into lines along with the offset where each one starts */
+function linesWithOffsets(text: string) {
+  let offset = 0
+  return text.split('\n').map((line) => {
+    const result = { line, offset }
+    offset += line.length + 1
+    return result
+  })
+}
+
+/**
+ * Headings marked by a run of some character at the start of the line, where
+ * the length of the run is the level. Anything inside code blocks is skipped
+ * so shell comments don't turn into headings. Without `fenceEnd`, a block is
+ * closed by a fence at least as long as the one that opened it.
+ */
+function prefixHeadings(marker: RegExp, fence: RegExp, fenceEnd?: RegExp) {
+  return (text: string) => {
+    const headings: Heading[] = []
+    let openFence: string | undefined
+    for (const { line, offset } of linesWithOffsets(text)) {
+      const fenceMatch = line.match(fence)?.[0]
+      if (openFence) {
+        const closes = fenceEnd ? fenceEnd.test(line) : fenceMatch?.startsWith(openFence)
+        if (closes) openFence = undefined
+      } else if (fenceMatch) {
+        openFence = fenceMatch
+      } else {
+        const m = line.match(marker)
+        if (m) headings.push({ level: m[1].length, title: m[2].trim(), offset })
+      }
+    }
+    return headings
+  }
+}
+
+// Section adornments can be any run of non-alphanumeric printable ASCII
+const rstAdornment = /^([!-\/:-@\[-`{-~])\1+\s*$/
+
+/**
+ * reStructuredText titles are underlined (and optionally overlined) with
+ * punctuation at least as long as the title. Levels aren't fixed: each new
+ * adornment style gets the next level down in the order they appear.
+ */
+function rstHeadings(text: string): Heading[] {
+  const lines = linesWithOffsets(text)
+  const styles: string[] = []
+  const headings: Heading[] = []
+  for (let i = 1; i < lines.length; i++) {
+    const under = lines[i].line.trimEnd()
+    const m = under.match(rstAdornment)
+    const title = lines[i - 1].line
+    if (!m || !title.trim() || rstAdornment.test(title)) continue
+    const over = i >= 2 && lines[i - 2].line.trimEnd() === under
+    // without an overline the title can't be indented, otherwise it's a block quote
+    if (!over && /^\s/.test(title)) continue
+    if (under.length < title.trim().length) continue
+    const style = over ? `over${m[1]}` : m[1]
+    if (!styles.includes(style)) styles.push(style)
+    headings.push({
+      level: styles.indexOf(style) + 1,
+      title: title.trim(),
+      offset: lines[over ? i - 2 : i - 1].offset,
+    })
+  }
+  return headings
+}
+
+const orgPrefixHeadings = prefixHeadings(/^(\*+)\s+(.*)/, /^\s*#\+begin_/i, /^\s*#\+end_/i)
+
+// Org headings can end with :tags:, which aren't part of the title
+const orgHeadings = (text: string) =>
+  orgPrefixHeadings(text).map((h) => ({
+    ...h,
+    title: h.title.replace(/\s+:[\w@#%:]+:$/, ''),
+  }))
+
+/**
+ * Plain text has no real headings, but a line underlined with === or --- is a
+ * common convention, so treat those as level 1 and 2.
+ */
+function textHeadings(text: string): Heading[] {
+  const lines = linesWithOffsets(text)
+  const headings: Heading[] = []
+  for (let i = 1; i < lines.length; i++) {
+    const m = lines[i].line.match(/^(={3,}|-{3,})\s*$/)
+    const title = lines[i - 1].line.trim()
+    if (!m || !title || /^[=-]+$/.test(title)) continue
+    headings.push({ level: m[1][0] === '=' ? 1 : 2, title, offset: lines[i - 1].offset })
+  }
+  return headings
+}
+
+const markdownHeadings = prefixHeadings(/^(#+)\s+(.*)/, /^(`{3,}|~{3,})/)
+
+const namedEntities: Record<string, string> = {
+  amp: '&',
+  lt: '<',
+  gt: '>',
+  quot: '"',
+  apos: "'",
+  nbsp: ' ',
+  ndash: '–',
+  mdash: '—',
+  hellip: '…',
+  lsquo: '‘',
+  rsquo: '’',
+  ldquo: '“',
+  rdquo: '”',
+  copy: '©',
+  para: '¶',
+}
+
+function decodeEntities(text: string) {
+  return text.replace(/&(#x[0-9a-f]+|#\d+|\w+);/gi, (entity, name: string) => {
+    if (name[0] !== '#') return namedEntities[name.toLowerCase()] ?? entity
+    const code = name[1].toLowerCase() === 'x'
+      ? parseInt(name.slice(2), 16)
+      : parseInt(name.slice(1), 10)
+    return code <= 0x10ffff ? String.fromCodePoint(code) : entity
+  })
+}
+
+const stripTags = (html: string) => html.replace(/<[^>]*>/g, '')
+
+// Elements that are page chrome or not text at all
+const dropTags = [
+  'script',
+  'style',
+  'noscript',
+  'template',
+  'svg',
+  'nav',
+  'header',
+  'footer',
+  'aside',
+  'form',
+  'button',
+  'iframe',
+]
+const dropElements = new RegExp(`<(${dropTags.join('|')})\\b[\\s\\S]*?</\\1>`, 'gi')
+
+// Docusaurus and many themes put the page's <h1> in a <header>, so headings
+// are the one part of a header worth keeping
+const keepHeadings = (element: string, tag: string) =>
+  tag.toLowerCase() === 'header'
+    ? (element.match(/<h([1-6])\b[\s\S]*?<\/h\1>/gi) ?? []).join('\n')
+    : ''
+
+const blockTags = /<\/?(p|div|section|[uod]l|d[td]|table|tr|blockquote|figure)\b[^>]*>/gi
+
+/**
+ * Convert HTML (typically a page from a static docs site) to Markdown-ish
+ * text: headings become `#` headings, `<pre>` becomes fenced code, and
+ * navigation, scripts, and styles are dropped. If the page marks its content
+ * with `<main>` or `<article>`, only that part is kept. Regexes rather than a
+ * real parser, but generated docs pages are regular enough for that.
+ */
+function htmlToText(html: string): string {
+  const content = html.match(/<main\b[^>]*>([\s\S]*?)<\/main>/i)?.[1] ??
+    html.match(/<article\b[^>]*>([\s\S]*?)<\/article>/i)?.[1] ??
+    html.match(/<body\b[^>]*>([\s\S]*)<\/body>/i)?.[1] ??
+    html
+
+  // pull out code blocks first so the tag stripping below can't touch their contents
+  const blocks: string[] = []
+  const text = content
+    .replace(/<!--[\s\S]*?-->/g, '')
+    .replace(dropElements, keepHeadings)
+    .replace(/<pre\b[^>]*>([\s\S]*?)<\/pre>/gi, (_, code: string) => {
+      blocks.push('```\n' + decodeEntities(stripTags(code)).replace(/\n+$/, '') + '\n```')
+      return `\n\n\uE000${blocks.length - 1}\uE000\n\n`
+    })
+    .replace(/<h([1-6])\b[^>]*>([\s\S]*?)<\/h\1>/gi, (_, level: string, inner: string) => {
+      // Sphinx and friends put a ¶ permalink in every heading
+      const title = stripTags(inner).replace(/[¶\u200b]/g, '').replace(/\s+/g, ' ').trim()
+      return `\n\n${'#'.repeat(Number(level))} ${title}\n\n`
+    })
+    .replace(/<code\b[^>]*>([\s\S]*?)<\/code>/gi, '`$1`')
+    .replace(/<li\b[^>]*>/gi, '\n- ')
+    .replace(/<br\s*\/?>/gi, '\n')
+    .replace(/<\/t[dh]>/gi, ' | ')
+    .replace(blockTags, '\n\n')
+
+  return decodeEntities(stripTags(text))
+    .split('\n')
+    .map((line) => line.replace(/[ \t]+/g, ' ').trim())
+    .join('\n')
+    .replace(/\n{3,}/g, '\n\n')
+    .replace(/\uE000(\d+)\uE000/g, (_, i: string) => blocks[Number(i)])
+    .trim()
+}
+
+type MultilineString = string | string[]
+
+interface NotebookOutput {
+  output_type: 'stream' | 'execute_result' | 'display_data' | 'error'
+  text?: MultilineString
+  data?: Record<string, MultilineString>
+  ename?: string
+  evalue?: string
+}
+
+interface NotebookCell {
+  cell_type: 'markdown' | 'code' | 'raw'
+  source: MultilineString
+  outputs?: NotebookOutput[]
+}
+
+interface Notebook {
+  cells: NotebookCell[]
+  metadata?: {
+    kernelspec?: { language?: string }
+    language_info?: { name?: string }
+  }
+}
+
+const joinLines = (s: MultilineString) => Array.isArray(s) ? s.join('') : s
+
+// Text outputs are cut off after this, since long ones are usually data dumps
+const MAX_OUTPUT_CHARS = 2000
+
+/** Only plain text survives: images, HTML tables, and widgets are dropped */
+function outputText(output: NotebookOutput): string | undefined {
+  if (output.output_type === 'stream') return output.text && joinLines(output.text)
+  if (output.output_type === 'error') return `${output.ename}: ${output.evalue}`
+  const plain = output.data?.['text/plain']
+  return plain && joinLines(plain)
+}
+
+/**
+ * Markdown cells come through as-is and code cells become fenced blocks, so
+ * the result can be treated as Markdown. Outputs are dropped unless
+ * `notebookOutputs` is on.
+ */
+function notebookToText(raw: string, { notebookOutputs }: FormatOptions): string {
+  const notebook: Notebook = JSON.parse(raw)
+  const language = notebook.metadata?.kernelspec?.language ??
+    notebook.metadata?.language_info?.name ?? ''
+  return notebook.cells.map((cell) => {
+    const source = joinLines(cell.source).trimEnd()
+    if (cell.cell_type !== 'code') return source
+    const parts = source ? ['```' + language + '\n' + source + '\n```'] : []
+    if (notebookOutputs) {
+      let output = (cell.outputs ?? []).map(outputText).filter((t) => !!t).join('\n')
+        .trimEnd()
+      if (output.length > MAX_OUTPUT_CHARS) {
+        output = output.slice(0, MAX_OUTPUT_CHARS) + '\n[output truncated]'
+      }
+      if (output) parts.push('```\n' + output + '\n```')
+    }
+    return parts.join('\n\n')
+  }).filter((text) => !!text).join('\n\n')
+}
+
+/////////////////////////////
+// SOURCE CODE
+/////////////////////////////
+
+/** A doc comment and the name of the item it documents, if any */
+interface DocComment {
+  heading?: string
+  level: number
+  text: string
+}
+
+// Top-level items get ## headings and anything indented (methods, fields) ###
+const itemLevel = (line: string) => /^\s/.test(line) ? 3 : 2
+
+/** Push any headings inside a doc comment down below the item's own heading */
+function demoteHeadings(text: string, by: number) {
+  const offsets = new Set(markdownHeadings(text).map((h) => h.offset))
+  return linesWithOffsets(text)
+    .map(({ line, offset }) => offsets.has(offset) ? '#'.repeat(by) + line : line)
+    .join('\n')
+}
+
+/**
+ * Doc comments without an item (module docs) come through as-is. The rest
+ * each get a Markdown heading with the item name, so the result can be
+ * treated as Markdown.
+ */
+function renderDocComments(comments: DocComment[]) {
+  return comments
+    .filter((c) => c.text)
+    .map(({ heading, level, text }) =>
+      heading ? `${'#'.repeat(level)} ${heading}\n\n${demoteHeadings(text, level)}` : text
+    )
+    .join('\n\n')
+}
+
+/** Name an item from its first line, e.g. `fn parse` or `class Config` */
+function itemName(line: string, pattern: RegExp) {
+  const m = line.trim().match(pattern)
+  if (m?.[2]) return `${m[1]} ${m[2]}`
+  // fall back to the line itself, e.g. `impl Display for Config` or an enum variant
+  return line.trim().replace(/\s*(\{\}?|[,;])\s*$/, '').slice(0, 80)
+}
+
+const rustItem =
+  /^(?:pub(?:\([^)]*\))?\s+)?(?:(?:async|const|unsafe|default|extern\s+"[^"]*")\s+)*(fn|struct|enum|trait|mod|type|const|static|union|macro_rules!)\s*([A-Za-z_]\w*)/
+
+/** Rust `//!` module docs and `///` item docs */
+function rustDocs(raw: string): string {
+  const comments: DocComment[] = []
+  const moduleDocs: string[] = []
+  let pending: string[] = []
+  for (const line of raw.split('\n')) {
+    const trimmed = line.trim()
+    if (trimmed.startsWith('//!')) {
+      moduleDocs.push(trimmed.replace(/^\/\/! ?/, ''))
+    } else if (trimmed.startsWith('///') && !trimmed.startsWith('////')) {
+      pending.push(trimmed.replace(/^\/\/\/ ?/, ''))
+    } else if (pending.length > 0 && trimmed && !/^(#\[|\/\/)/.test(trimmed)) {
+      // attributes and plain comments between the docs and the item are skipped
+      comments.push({
+        heading: itemName(line, rustItem),
+        level: itemLevel(line),
+        text: pending.join('\n').trim(),
+      })
+      pending = []
+    }
+  }
+  return renderDocComments([{ level: 0, text: moduleDocs.join('\n').trim() }, ...comments])
+}
+
+/** Remove the common indentation from all lines after the first */
+function dedentDocstring(lines: string[]) {
+  const indents = lines.slice(1)
+    .filter((line) => line.trim())
+    .map((line) => line.match(/^\s*/)![0].length)
+  const indent = Math.min(...indents)
+  return [lines[0], ...lines.slice(1).map((line) => line.slice(indent))].join('\n').trim()
+}
+
+/** The docstring starting at line `i`, if there is one */
+function readDocstring(lines: string[], i: number): string | undefined {
+  const m = lines[i]?.match(/^\s*[rRuU]?("""|''')(.*)$/)
+  if (!m) return undefined
+  const [, quote, rest] = m
+  if (rest.includes(quote)) return rest.slice(0, rest.indexOf(quote)).trim()
+  const body = [rest]
+  for (let j = i + 1; j < lines.length; j++) {
+    const end = lines[j].indexOf(quote)
+    if (end >= 0) return dedentDocstring([...body, lines[j].slice(0, end)])
+    body.push(lines[j])
+  }
+  return undefined
+}
+
+/** Python module, class, and function docstrings */
+function pythonDocs(raw: string): string {
+  const lines = raw.split('\n')
+  const comments: DocComment[] = []
+
+  // the module docstring has to be the first statement
+  const first = lines.findIndex((line) => line.trim() && !line.trim().startsWith('#'))
+  const moduleDoc = readDocstring(lines, first)
+  if (moduleDoc) comments.push({ level: 0, text: moduleDoc })
+
+  lines.forEach((line, i) => {
+    const m = line.match(/^\s*(async\s+def|def|class)\s+(\w+)/)
+    if (!m) return
+    // signatures can span lines, so find the colon that ends it
+    let j = i
+    while (j < lines.length && !/:\s*(#.*)?$/.test(lines[j])) j++
+    j++
+    while (j < lines.length && !lines[j].trim()) j++
+    const text = readDocstring(lines, j)
+    if (text) {
+      const heading = `${m[1].replace(/\s+/, ' ')} ${m[2]}`
+      comments.push({ heading, level: itemLevel(line), text })
+    }
+  })
+  return renderDocComments(comments)
+}
+
+const jsItem =
+  /^(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(function\*?|class|interface|type|const|let|var|enum|namespace)\s+([\w$]+)/
+const jsMember =
+  /^(?:(?:public|private|protected|static|readonly|abstract|override|async|get|set)\s+)*\*?([\w$#]+)\s*[?!]?\s*[(:=<]/
+
+/** JSDoc blocks in JavaScript and TypeScript */
+function jsDocs(raw: string): string {
+  const comments: DocComment[] = []
+  for (const m of raw.matchAll(/\/\*\*(?!\/)([\s\S]*?)\*\//g)) {
+    const text = m[1].split('\n')
+      .map((line) => line.replace(/^\s*\* ?/, ''))
+      .join('\n')
+      .trim()
+    if (/@(module|file|fileoverview)\b/.test(text)) {
+      comments.push({ level: 0, text })
+      continue
+    }
+    // the item is the next line of code, skipping decorators
+    const line = raw.slice(m.index + m[0].length)
+      .split('\n')
+      .find((l) => l.trim() && !l.trim().startsWith('@'))
+    if (!line) continue
+    const heading = jsItem.test(line.trim())
+      ? itemName(line, jsItem)
+      : line.trim().match(jsMember)?.[1]
+    if (heading) comments.push({ heading, level: itemLevel(line), text })
+  }
+  return renderDocComments(comments)
+}
+
+/////////////////////////////
+// MAN PAGES
+/////////////////////////////
+
+// Special characters that come up in man pages, written \(xx or \[xx]
+const roffChars: Record<string, string> = {
+  em: '—',
+  en: '–',
+  hy: '-',
+  mi: '-',
+  pl: '+',
+  mu: '×',
+  bu: '•',
+  lq: '“',
+  rq: '”',
+  oq: '‘',
+  cq: '’',
+  dq: '"',
+  aq: "'",
+  ga: '`',
+  ha: '^',
+  ti: '~',
+  rs: '\\',
+  sl: '/',
+  ba: '|',
+  bv: '|',
+  co: '©',
+  rg: '®',
+  tm: '™',
+  dg: '†',
+  '->': '→',
+  '<-': '←',
+  '<=': '≤',
+  '>=': '≥',
+}
+
+// Strings predefined by the man macros, written \*x, \*(xx, or \*[xx]
+const roffStrings: Record<string, string> = { lq: '“', rq: '”', R: '®', Tm: '™' }
+
+// One-character escapes that don't stand for themselves
+const roffSingles: Record<string, string> = {
+  e: '\\',
+  t: '\t',
+  ' ': ' ',
+  '~': ' ',
+  '0': ' ',
+  '&': '',
+  '|': '',
+  '^': '',
+  '%': '',
+  ':': '',
+  ')': '',
+  c: '',
+  d: '',
+  u: '',
+  r: '',
+}
+
+const roffEscape = new RegExp(
+  [
+    /\\\((..)/, // \(xx
+    /\\\[([^\]]*)\]/, // \[name]
+    /\\\*(?:\((..)|\[([^\]]*)\]|(.))/, // \*x, \*(xx, \*[name]
+    /\\[fFn](?:\(..|\[[^\]]*\]|.)/, // font changes and number registers
+    /\\s[+-]?\d+/, // size changes
+    /\\[hvwlLoXDbZ]'[^']*'/, // motions and the like, with quoted arguments
+    /\\(.)/,
+  ].map((r) => r.source).join('|'),
+  'g',
+)
+
+/** Expand escapes in a line of roff text, dropping font changes and comments */
+function roffInline(text: string) {
+  return text.replace(/\\".*/, '').replace(
+    roffEscape,
+    (_, char2, charName, str2, strName, str1, single) => {
+      const char = char2 ?? charName
+      if (char !== undefined) {
+        if (char in roffChars) return roffChars[char]
+        // \[u00E9] and the like
+        const unicode = char.match(/^u([0-9A-F]{4,6})$/)?.[1]
+        return unicode ? String.fromCodePoint(parseInt(unicode, 16)) : ''
+      }
+      const str = str2 ?? strName ?? str1
+      if (str !== undefined) return roffStrings[str] ?? ''
+      if (single === undefined) return ''
+      return roffSingles[single] ?? single
+    },
+  )
+}
+
+/** Macro arguments are separated by spaces, with "" for a quote inside quotes */
+const roffArgs = (text: string) =>
+  [...text.matchAll(/"((?:[^"]|"")*)"?|(\S+)/g)]
+    .map(([, quoted, bare]) => quoted?.replaceAll('""', '"') ?? bare)
+
+/**
+ * Output shared by the man(7) and mdoc(7) renderers: lines of Markdown, with
+ * helpers for paragraph breaks and code fences
+ */
+function roffWriter() {
+  const out: string[] = []
+  let inFence = false
+  const paragraph = () => {
+    if (!inFence && out.length > 0 && out.at(-1) !== '') out.push('')
+  }
+  const fence = (open: boolean) => {
+    if (open === inFence) return
+    if (open) paragraph()
+    out.push('```')
+    inFence = open
+  }
+  const done = () => {
+    fence(false)
+    return out.join('\n').replace(/\n{3,}/g, '\n\n').trim()
+  }
+  return { out, paragraph, fence, done }
+}
+
+/**
+ * Split roff source into text lines and macro calls, skipping macro definitions
+ * and `.ig` blocks. `inline` renders each macro argument.
+ */
+function* roffLines(raw: string, inline = roffInline) {
+  let skipping = false
+  // a backslash at the end of a line continues it on the next one
+  for (const line of raw.replace(/\\\n/g, '').split('\n')) {
+    if (skipping) {
+      if (line.startsWith('..')) skipping = false
+      continue
+    }
+    const m = line.match(/^[.'][ \t]*(\S*)[ \t]*(.*)/)
+    if (m && ['de', 'de1', 'am', 'ig'].includes(m[1])) {
+      skipping = true
+      continue
+    }
+    yield m ? { macro: m[1], args: roffArgs(m[2]).map(inline) } : { text: line }
+  }
+}
+
+/**
+ * Render a man page written with the man(7) macros to text. `.TH`, `.SH`, and
+ * `.SS` become Markdown headings so they're found like any other, no-fill
+ * blocks (usually examples) become code blocks, and font changes are dropped.
+ * Other requests are skipped, which loses a little from pages using fancier
+ * roff than usual.
+ */
+function manToText(raw: string): string {
+  const { out, paragraph, fence, done } = roffWriter()
+  // .SH and .SS can put the title on the next line instead of taking arguments
+  let pendingHeading: string | undefined
+
+  const text = (line: string) => {
+    if (pendingHeading && line.trim()) {
+      out.push(`${pendingHeading} ${line.trim()}`, '')
+      pendingHeading = undefined
+    } else {
+      out.push(line)
+    }
+  }
+
+  for (const line of roffLines(raw)) {
+    if (line.text !== undefined) {
+      text(roffInline(line.text))
+      continue
+    }
+    const { macro, args } = line
+    switch (macro) {
+      case 'TH':
+        out.push(`# ${args[0]}${args[1] ? `(${args[1]})` : ''}`, '')
+        break
+      case 'SH':
+      case 'SS': {
+        fence(false)
+        paragraph()
+        const marker = macro === 'SH' ? '##' : '###'
+        if (args.length > 0) out.push(`${marker} ${args.join(' ')}`, '')
+        else pendingHeading = marker
+        break
+      }
+      case 'PP':
+      case 'P':
+      case 'LP':
+      case 'TP':
+      case 'TQ':
+      case 'sp':
+        paragraph()
+        break
+      case 'IP':
+        paragraph()
+        if (args[0]) text(args[0])
+        break
+      case 'nf':
+      case 'EX':
+        fence(true)
+        break
+      case 'fi':
+      case 'EE':
+        fence(false)
+        break
+      case 'B':
+      case 'I':
+      case 'SM':
+      case 'SB':
+        text(args.join(' '))
+        break
+      case 'BR':
+      case 'BI':
+      case 'IB':
+      case 'IR':
+      case 'RB':
+      case 'RI':
+        text(args.join(''))
+        break
+      case 'UR':
+      case 'MT':
+        if (args[0]) text(`<${args[0]}>`)
+        break
+      case 'SY':
+        text(args.join(' '))
+        break
+      case 'OP':
+        text(`[${args.join(' ')}]`)
+        break
+    }
+  }
+  return done()
+}
+
+// mdoc(7) macros that can be called from the arguments of other macros
+const mdocCallable = new Set(
+  ('Ac Ad An Ao Ap Aq Ar At Bc Bo Bq Brc Bro Brq Bsx Bx Cd Cm Dc Do Dq Dv Dx Ec Em ' +
+    'En Eo Er Es Ev Fa Fc Fl Fn Fo Fr Ft Fx Ic In Lb Li Lk Ms Mt Nm No Ns Nx Oc Oo Op ' +
+    'Ot Ox Pa Pc Pf Po Pq Qc Ql Qo Qq Sc Sh So Sq Ss St Sx Sy Ta Tn Ux Va Vt Xc Xo Xr')
+    .split(' '),
+)
+
+// Macros that wrap the rest of the line
+const mdocEnclosures: Record<string, [string, string]> = {
+  Aq: ['<', '>'],
+  Bq: ['[', ']'],
+  Brq: ['{', '}'],
+  Dq: ['“', '”'],
+  Op: ['[', ']'],
+  Pq: ['(', ')'],
+  Ql: ['‘', '’'],
+  Qq: ['"', '"'],
+  Sq: ['‘', '’'],
+}
+
+// Macros that open or close a bracket that spans several macros or lines
+const mdocOpeners: Record<string, string> = {
+  Ao: '<',
+  Bo: '[',
+  Bro: '{',
+  Do: '“',
+  Oo: '[',
+  Po: '(',
+  Qo: '"',
+  So: '‘',
+}
+const mdocClosers: Record<string, string> = {
+  Ac: '>',
+  Bc: ']',
+  Brc: '}',
+  Dc: '”',
+  Oc: ']',
+  Pc: ')',
+  Qc: '"',
+  Sc: '’',
+}
+
+const mdocSystems: Record<string, string> = {
+  At: 'AT&T UNIX',
+  Bsx: 'BSD/OS',
+  Bx: 'BSD',
+  Dx: 'DragonFly',
+  Fx: 'FreeBSD',
+  Nx: 'NetBSD',
+  Ox: 'OpenBSD',
+  Ux: 'UNIX',
+}
+
+// punctuation that hugs the word before it or after it
+const isCloser = (t: string) => /^[.,:;)\]?!]$/.test(t)
+const isOpener = (t: string) => /^[([]$/.test(t)
+
+// `\&` keeps an argument from counting as punctuation or a macro. It's turned
+// into this until the line is rendered.
+const ZWSP = '\u200b'
+
+/**
+ * Render the arguments of an mdoc line, which can call other macros:
+ * `.Op Fl o Ar file` is `[-o file]`. `name` stands in for a bare `.Nm`, and
+ * with `spacing` off (`.Sm off`) words run together.
+ */
+function mdocPhrase(tokens: string[], name: string, spacing = true): string {
+  let out = ''
+  let glue = true // no space before the next word
+  const put = (word: string) => {
+    if (!word) return
+    out += glue || !spacing || isCloser(word) ? word : ` ${word}`
+    glue = isOpener(word)
+  }
+  let i = 0
+  // a macro's own arguments run until the next macro
+  const args = () => {
+    const start = i
+    while (i < tokens.length && !mdocCallable.has(tokens[i])) i++
+    return tokens.slice(start, i)
+  }
+  // punctuation right after a macro isn't one of its arguments
+  const words = (a: string[]) => a.filter((t) => !isCloser(t) && !isOpener(t))
+
+  while (i < tokens.length) {
+    const token = tokens[i++]
+    if (!mdocCallable.has(token)) {
+      put(token)
+      continue
+    }
+    if (token in mdocEnclosures) {
+      let end = tokens.length
+      while (end > i && isCloser(tokens[end - 1])) end--
+      const [open, close] = mdocEnclosures[token]
+      put(open + mdocPhrase(tokens.slice(i, end), name, spacing) + close)
+      i = end
+      continue
+    }
+    if (token in mdocOpeners) {
+      put(mdocOpeners[token])
+      glue = true
+      args().forEach(put)
+      continue
+    }
+    if (token in mdocClosers) {
+      out += mdocClosers[token]
+      glue = false
+      args().forEach(put)
+      continue
+    }
+    const a = args()
+    switch (token) {
+      case 'Ap':
+        out += "'"
+        glue = true
+        a.forEach(put)
+        break
+      case 'Ns':
+        glue = true
+        a.forEach(put)
+        break
+      case 'Fl':
+        if (words(a).length === 0) put('-')
+        a.forEach((t) => put(isCloser(t) || isOpener(t) ? t : `-${t}`))
+        break
+      case 'Ar':
+        if (words(a).length === 0) put('file ...')
+        a.forEach(put)
+        break
+      case 'Nm':
+        if (words(a).length === 0) put(name)
+        a.forEach(put)
+        break
+      case 'Xr':
+        put(a[1] && !isCloser(a[1]) ? `${a[0]}(${a[1]})` : a[0])
+        a.slice(a[1] && !isCloser(a[1]) ? 2 : 1).forEach(put)
+        break
+      case 'Fn':
+        put(`${a[0]}(${words(a.slice(1)).join(', ')})`)
+        a.slice(1).filter(isCloser).forEach(put)
+        break
+      case 'In':
+        put(`<${a[0]}>`)
+        a.slice(1).forEach(put)
+        break
+      case 'Pf':
+        put(a[0])
+        glue = true
+        a.slice(1).forEach(put)
+        break
+      case 'Ta':
+        out += '\t'
+        glue = true
+        a.forEach(put)
+        break
+      default:
+        if (token in mdocSystems) put(mdocSystems[token])
+        a.forEach(put)
+    }
+  }
+  return out
+}
+
+/**
+ * Render a man page written with the mdoc(7) macros, which is what BSD and
+ * macOS use, to text. Sections become headings as with man(7), literal
+ * displays become code blocks, lists keep their tags or bullets, and inline
+ * macros are rendered the way `mandoc` would show them in plain text.
+ */
+function mdocToText(raw: string): string {
+  const { out, paragraph, fence, done } = roffWriter()
+  let name = ''
+  // list types of the open .Bl lists, and whether each open .Bd is a code block
+  const lists: { type: string; n: number }[] = []
+  const displays: boolean[] = []
+  let prefix = '' // bullet or number for the next line of a list item
+  let spacing = true
+
+  const text = (line: string) => {
+    out.push(prefix + line)
+    prefix = ''
+  }
+  const phrase = (args: string[]) => mdocPhrase(args, name, spacing)
+  const inline = (arg: string) => roffInline(arg.replaceAll('\\&', ZWSP))
+
+  for (const line of roffLines(raw, inline)) {
+    if (line.text !== undefined) {
+      text(roffInline(line.text))
+      continue
+    }
+    const { macro, args } = line
+    switch (macro) {
+      case 'Dt':
+        out.push(`# ${args[0]}${args[1] ? `(${args[1]})` : ''}`, '')
+        break
+      case 'Sh':
+      case 'Ss':
+        fence(false)
+        paragraph()
+        out.push(`${macro === 'Sh' ? '##' : '###'} ${phrase(args)}`, '')
+        break
+      case 'Nm':
+        name ||= args[0] ?? ''
+        text(phrase(['Nm', ...args]))
+        break
+      case 'Nd':
+        text(`— ${phrase(args)}`)
+        break
+      case 'Pp':
+      case 'Lp':
+      case 'sp':
+        paragraph()
+        break
+      case 'Bd': {
+        const literal = args.includes('-literal') || args.includes('-unfilled')
+        displays.push(literal)
+        if (literal) fence(true)
+        else paragraph()
+        break
+      }
+      case 'Ed':
+        if (displays.pop()) fence(false)
+        else paragraph()
+        break
+      case 'D1':
+        text(phrase(args))
+        break
+      case 'Dl':
+        text(`    ${phrase(args)}`)
+        break
+      case 'Bl':
+        paragraph()
+        lists.push({ type: args.find((a) => a.startsWith('-')) ?? '-tag', n: 0 })
+        break
+      case 'El':
+        lists.pop()
+        paragraph()
+        break
+      case 'It': {
+        const list = lists.at(-1) ?? { type: '-tag', n: 0 }
+        paragraph()
+        prefix = list.type === '-enum'
+          ? `${++list.n}. `
+          : ['-bullet', '-dash', '-hyphen'].includes(list.type)
+          ? '- '
+          : ''
+        if (args.length > 0) text(phrase(args))
+        break
+      }
+      case 'nf':
+        fence(true)
+        break
+      case 'fi':
+        fence(false)
+        break
+      case 'Sm':
+        spacing = args[0] === undefined ? !spacing : args[0] !== 'off'
+        break
+      case 'Ex':
+        text(`The ${args[1] ?? name} utility exits 0 on success, and >0 on error.`)
+        break
+      case 'Rv':
+        text('The function returns 0 on success, and -1 with errno set on error.')
+        break
+      default:
+        // references like %A and %T, and any macro that can also be called inline
+        if (macro.startsWith('%') || mdocCallable.has(macro)) {
+          text(phrase(macro.startsWith('%') ? args : [macro, ...args]))
+        }
+    }
+  }
+  return done().replaceAll(ZWSP, '')
+}
+
+/** Render a man page to text, whichever macro package it's written with */
+function roffToText(raw: string): string {
+  return /^\.\s*(Dd|Dt|Sh)\s/m.test(raw) ? mdocToText(raw) : manToText(raw)
+}
+
+/////////////////////////////
+// HELP TEXT
+/////////////////////////////
+
+/**
+ * `--help` output has no markup, but its sections are almost always an
+ * unindented line ending in a colon (`Options:`, `Commands:`), so those become
+ * headings. Everything else is kept as it is.
+ */
+function helpToText(raw: string): string {
+  return raw.replace(/\r\n/g, '\n').split('\n').map((line) => {
+    const m = line.match(/^(\S[^:]{0,60}):\s*$/)
+    return m ? `\n# ${m[1]}\n` : line.trimEnd()
+  }).join('\n').replace(/\n{3,}/g, '\n\n').trim()
+}
+
+/////////////////////////////
+// REGISTRY
+/////////////////////////////
+
+export const formats: Record<string, Format> = {
+  markdown: {
+    exts: ['md', 'mdx', 'markdown'],
+    frontmatter: true,
+    headings: markdownHeadings,
+  },
+  asciidoc: {
+    exts: ['adoc', 'asciidoc'],
+    frontmatter: true,
+    headings: prefixHeadings(/^(=+)\s+(.*)/, /^(-{4,}|\.{4,})(?=\s*$)/),
+  },
+  rst: {
+    exts: ['rst'],
+    headings: rstHeadings,
+  },
+  org: {
+    exts: ['org'],
+    headings: orgHeadings,
+  },
+  text: {
+    exts: ['txt'],
+    headings: textHeadings,
+  },
+  html: {
+    exts: ['html', 'htm'],
+    toText: htmlToText,
+    headings: markdownHeadings,
+  },
+  notebook: {
+    exts: ['ipynb'],
+    toText: notebookToText,
+    headings: markdownHeadings,
+  },
+  rust: {
+    exts: ['rs'],
+    source: true,
+    toText: rustDocs,
+    headings: markdownHeadings,
+  },
+  python: {
+    exts: ['py', 'pyi'],
+    source: true,
+    toText: pythonDocs,
+    headings: markdownHeadings,
+  },
+  javascript: {
+    exts: ['js', 'mjs', 'cjs', 'jsx', 'ts', 'mts', 'cts', 'tsx'],
+    source: true,
+    toText: jsDocs,
+    headings: markdownHeadings,
+  },
+  // Man pages are found by name rather than extension (see `manPages` in main.ts)
+  man: {
+    exts: [],
+    toText: roffToText,
+    headings: markdownHeadings,
+  },
+  // Help text comes from running the command (see `helpDocs` in main.ts)
+  help: {
+    exts: [],
+    toText: helpToText,
+    headings: markdownHeadings,
+  },
+}
+
+/** Name of the format for a path, based on its extension */
+export function formatFor(path: string, { sourceCode }: FormatOptions): string | undefined {
+  const ext = extname(path).slice(1).toLowerCase()
+  return Object.keys(formats).find((name) =>
+    formats[name].exts.includes(ext) && (sourceCode || !formats[name].source)
+  )
+}
@@ -0,0 +1,358 @@
+import { assertEquals } from 'jsr:@std/assert@1'
+import { type FormatOptions, formats } from './formats.ts'
+
+const toText = (format: string, raw: string, opts: Partial<FormatOptions> = {}) =>
+  formats[format].toText!(raw, { notebookOutputs: false, sourceCode: false, ...opts })
+
+Deno.test('man(7) pages get headings, code blocks, and plain text', () => {
+  const page = String.raw`.TH GIT 1 "2024" "Git" "Git Manual"
+.SH NAME
+git \- the stupid content tracker
+.SH "SYNOPSIS"
+.nf
+\fBgit\fR [\-v | \-\-version]
+.fi
+.SH
+DESCRIPTION
+Git is \(lqfast\(rq.
+.TP
+.BR \-\-foo =bar
+Does foo\&.
+.SS Sub section
+Text.`
+  assertEquals(
+    toText('man', page),
+    `# GIT(1)
+
+## NAME
+
+git - the stupid content tracker
+
+## SYNOPSIS
+
+\`\`\`
+git [-v | --version]
+\`\`\`
+
+## DESCRIPTION
+
+Git is “fast”.
+
+--foo=bar
+Does foo.
+
+### Sub section
+
+Text.`,
+  )
+})
+
+Deno.test('mdoc(7) pages render inline macros and lists', () => {
+  const page = String.raw`.Dd $Mdocdate$
+.Dt SCP 1
+.Os
+.Sh NAME
+.Nm scp
+.Nd secure file copy
+.Sh SYNOPSIS
+.Nm
+.Op Fl 346
+.Op Fl c Ar cipher
+.Ar source ... target
+.Sh DESCRIPTION
+.Nm
+copies files, see
+.Xr ssh 1 .
+.Bl -tag -width Ds
+.It Fl r
+Recursively copy.
+.El
+.Bd -literal -offset indent
+$ scp a b:
+.Ed
+.Sm off
+.Oo user @ Oc host : Op path
+.Sm on`
+  assertEquals(
+    toText('man', page),
+    `# SCP(1)
+
+## NAME
+
+scp
+— secure file copy
+
+## SYNOPSIS
+
+scp
+[-346]
+[-c cipher]
+source ... target
+
+## DESCRIPTION
+
+scp
+copies files, see
+ssh(1).
+
+-r
+Recursively copy.
+
+\`\`\`
+$ scp a b:
+\`\`\`
+[user@]host:[path]`,
+  )
+})
+
+Deno.test('HTML keeps the main content and the headings in its header', () => {
+  const html = '<html><body><nav>Menu</nav><main><article>' +
+    '<header><h1>Install</h1><span>2 min read</span></header>' +
+    '<p>Run <code>x</code>.</p><pre>a &lt; b</pre>' +
+    '</article></main><footer>Footer</footer></body></html>'
+  assertEquals(toText('html', html), '# Install\n\nRun `x`.\n\n```\na < b\n```')
+})
+
+Deno.test('help text gets its Section: lines as headings', () => {
+  const help = `Rust's package manager
+
+Usage: cargo [OPTIONS] [COMMAND]
+
+Options:
+  -V, --version  Print version info and exit
+
+Commands:
+    build, b    Compile the current package`
+  assertEquals(
+    toText('help', help),
+    `Rust's package manager
+
+Usage: cargo [OPTIONS] [COMMAND]
+
+# Options
+
+  -V, --version  Print version info and exit
+
+# Commands
+
+    build, b    Compile the current package`,
+  )
+})
+
+Deno.test('rst heading levels follow the order adornment styles first appear in', () => {
+  const rst = `=====
+Title
+=====
+
+Intro.
+
+Section
+=======
+
+  Not a title
+  -----------
+
+Sub
+---
+
+Short
+--
+
+Another
+=======
+`
+  assertEquals(
+    formats.rst.headings(rst).map(({ level, title }) => [level, title]),
+    [[1, 'Title'], [2, 'Section'], [3, 'Sub'], [2, 'Another']],
+  )
+})
+
+Deno.test('notebook outputs are only kept when asked for, and long ones are cut', () => {
+  const notebook = JSON.stringify({
+    metadata: { kernelspec: { language: 'python' } },
+    cells: [
+      { cell_type: 'markdown', source: ['# Analysis\n', 'Load the data.'] },
+      {
+        cell_type: 'code',
+        source: "print('hi')",
+        outputs: [
+          { output_type: 'stream', text: ['hi\n'] },
+          { output_type: 'display_data', data: { 'image/png': 'iVBOR' } },
+        ],
+      },
+      {
+        cell_type: 'code',
+        source: ['df'],
+        outputs: [
+          { output_type: 'execute_result', data: { 'text/plain': 'x'.repeat(2500) } },
+        ],
+      },
+      {
+        cell_type: 'code',
+        source: '1 / 0',
+        outputs: [
+          { output_type: 'error', ename: 'ZeroDivisionError', evalue: 'division by zero' },
+        ],
+      },
+    ],
+  })
+  const code = (src: string) => '```python\n' + src + '\n```'
+  const output = (text: string) => '```\n' + text + '\n```'
+  assertEquals(
+    toText('notebook', notebook),
+    ['# Analysis\nLoad the data.', code("print('hi')"), code('df'), code('1 / 0')]
+      .join('\n\n'),
+  )
+  assertEquals(
+    toText('notebook', notebook, { notebookOutputs: true }),
+    [
+      '# Analysis\nLoad the data.',
+      code("print('hi')"),
+      output('hi'),
+      code('df'),
+      output('x'.repeat(2000) + '\n[output truncated]'),
+      code('1 / 0'),
+      output('ZeroDivisionError: division by zero'),
+    ].join('\n\n'),
+  )
+})
+
+Deno.test('Rust sources keep doc comments under item headings', () => {
+  const rust = `//! Config loading.
+//!
+//! Reads TOML.
+
+/// A loaded config.
+#[derive(Debug)]
+pub struct Config {
+    /// Where it came from.
+    pub path: PathBuf,
+}
+
+impl Config {
+    /// Load from a path.
+    ///
+    /// # Errors
+    ///
+    /// If the file is missing.
+    pub async fn load(path: &Path) -> Result<Self> {
+        todo!()
+    }
+}
+
+// not a doc comment
+fn private() {}
+`
+  assertEquals(
+    toText('rust', rust),
+    `Config loading.
+
+Reads TOML.
+
+## struct Config
+
+A loaded config.
+
+### pub path: PathBuf
+
+Where it came from.
+
+### fn load
+
+Load from a path.
+
+#### Errors
+
+If the file is missing.`,
+  )
+})
+
+Deno.test('Python sources keep docstrings under def and class headings', () => {
+  const python = `"""Config loading."""
+
+import os
+
+
+class Config:
+    """A loaded config."""
+
+    def load(
+        self, path,
+    ):
+        """Load from a path.
+
+        Raises if the file is missing.
+        """
+
+    async def reload(self):
+        # no docstring
+        pass
+
+
+def helper():
+    '''Module-level helper.'''
+`
+  assertEquals(
+    toText('python', python),
+    `Config loading.
+
+## class Config
+
+A loaded config.
+
+### def load
+
+Load from a path.
+
+Raises if the file is missing.
+
+## def helper
+
+Module-level helper.`,
+  )
+})
+
+Deno.test('JS and TS sources keep JSDoc under declaration headings', () => {
+  const ts = `/**
+ * @module config
+ * Config loading.
+ */
+
+/** A loaded config. */
+export class Config {
+  /**
+   * Load from a path.
+   * @param path where to read
+   */
+  static async load(path: string): Promise<Config> {}
+
+  /** Where it came from */
+  readonly path: string
+}
+
+/** Default options */
+export const defaults = {}
+`
+  assertEquals(
+    toText('javascript', ts),
+    `@module config
+Config loading.
+
+## class Config
+
+A loaded config.
+
+### load
+
+Load from a path.
+@param path where to read
+
+### path
+
+Where it came from
+
+## const defaults
+
+Default options`,
+  )
+})
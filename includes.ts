@@ -0,0 +1,240 @@
+import { basename, dirname, isAbsolute, join, relative, resolve } from 'jsr:@std/path@1.0'
+
+/**
+ * A way of pulling one file into another. Each match of `directive` is
+ * replaced by (part of) the file it points to, with that file's own includes
+ * expanded in turn.
+ */
+interface IncludeSyntax {
+  directive: RegExp
+  /** Absolute path of the file a directive points to, if we can tell */
+  target(from: string, match: RegExpMatchArray): string | undefined
+  /** Cut an included file down to the part the directive asks for */
+  select(text: string, match: RegExpMatchArray): string
+  /** Adjust included text after its own includes have been expanded */
+  adjust?(text: string, match: RegExpMatchArray): string
+  /** What to put in place of a directive we couldn't follow */
+  unresolved(from: string, match: RegExpMatchArray): string
+}
+
+/** Parse a line spec like `1..5;10;20..-1` (1-based, inclusive) into a filter */
+export function lineRanges(spec: string) {
+  const ranges = spec.split(/[;,]/).filter((r) => r.trim()).map((r) => {
+    const [start, end] = r.split('..').map((n) => n.trim())
+    const from = Number(start) || 1
+    if (end === undefined) return [from, from]
+    return [from, end && end !== '-1' ? Number(end) : Infinity]
+  })
+  return (_: string, i: number) => ranges.some(([from, to]) => i + 1 >= from && i + 1 <= to)
+}
+
+/////////////////////////////
+// ASCIIDOC
+/////////////////////////////
+
+// Antora resource families and the directories they live in within a module
+const antoraFamilies: Record<string, string> = {
+  partial: 'partials',
+  example: 'examples',
+  page: 'pages',
+  attachment: 'attachments',
+}
+
+/**
+ * Resolve an Antora resource ID like `partial$foo.adoc` or `ROOT:example$x.rs`
+ * against the module of the including file. IDs that reach into other
+ * components or versions aren't supported.
+ */
+function antoraPath(from: string, target: string) {
+  const m = target.match(/^(?:([\w.-]+):)?(\w+)\$(.+)$/)
+  const moduleRoot = from.match(/^(.*\/modules\/)([^/]+)\//)
+  if (!m || !moduleRoot || !antoraFamilies[m[2]]) return undefined
+  const [, module, family, path] = m
+  return join(moduleRoot[1], module ?? moduleRoot[2], antoraFamilies[family], path)
+}
+
+/** Attributes of an include directive, e.g., `tag=setup,lines="1..5,10"` */
+function includeAttrs(attrList: string) {
+  const attrs: Record<string, string> = {}
+  for (const [, name, quoted, bare] of attrList.matchAll(/(\w+)=(?:"([^"]*)"|([^,]*))/g)) {
+    attrs[name] = (quoted ?? bare).trim()
+  }
+  return attrs
+}
+
+/**
+ * Keep the lines between `tag::name[]` and `end::name[]` for the tags asked
+ * for. `!name` excludes a tag instead, and `*` and `**` select all tagged
+ * lines and all lines. With only exclusions, untagged lines are kept too.
+ */
+export function selectTags(lines: string[], spec: string) {
+  const names = spec.split(/[;,]/).map((n) => n.trim()).filter((n) => n)
+  const wanted = names.filter((n) => !n.startsWith('!') && n !== '*' && n !== '**')
+  const unwanted = names.filter((n) => n.startsWith('!')).map((n) => n.slice(1))
+  const allTagged = names.includes('*') || names.includes('**') || wanted.length === 0
+  const untagged = names.includes('**') || (wanted.length === 0 && !names.includes('*'))
+  const open: string[] = []
+  return lines.filter((line) => {
+    const marker = line.match(/\b(tag|end)::([\w-]+)\[\]/)
+    if (marker) {
+      if (marker[1] === 'tag') open.push(marker[2])
+      else if (open.includes(marker[2])) open.splice(open.lastIndexOf(marker[2]), 1)
+      return false
+    }
+    if (open.some((t) => unwanted.includes(t))) return false
+    if (open.length === 0) return untagged
+    return allTagged || open.some((t) => wanted.includes(t))
+  })
+}
+
+/** Apply a `leveloffset` to section titles, e.g., `+1` turns `==` into `===` */
+function shiftLevels(text: string, offset: string) {
+  const by = parseInt(offset)
+  if (!by) return text
+  return text.replace(
+    /^(=+)(?=\s+\S)/gm,
+    (marks) => '='.repeat(Math.max(1, marks.length + by)),
+  )
+}
+
+const asciidoc: IncludeSyntax = {
+  directive: /^include::(\S*?)\[(.*)\][ \t]*$/gm,
+  target(from, [, target]) {
+    if (target.includes('{') || /^\w+:\/\//.test(target)) return undefined // attrs, URLs
+    if (target.includes('$')) return antoraPath(from, target)
+    return resolve(dirname(from), target)
+  },
+  select(text, [, , attrList]) {
+    const attrs = includeAttrs(attrList)
+    let lines = text.split('\n')
+    const tags = attrs.tags ?? attrs.tag
+    if (tags) lines = selectTags(lines, tags)
+    if (attrs.lines) lines = lines.filter(lineRanges(attrs.lines))
+    return lines.join('\n')
+  },
+  adjust(text, [, , attrList]) {
+    const { leveloffset } = includeAttrs(attrList)
+    return leveloffset ? shiftLevels(text, leveloffset) : text
+  },
+  unresolved(from, [directive, , attrList]) {
+    // same as Asciidoctor, including staying quiet about optional includes
+    if (includeAttrs(attrList).opts?.split(',').includes('optional')) return ''
+    return `Unresolved directive in ${basename(from)} - ${directive}`
+  },
+}
+
+/////////////////////////////
+// MDBOOK
+/////////////////////////////
+
+const anchorLine = /\bANCHOR(_END)?:\s*[\w-]+/
+
+/**
+ * mdBook's `{{#include file}}`, `file:anchor`, `file:5`, and `file:5:10` (where
+ * either end of the range can be left off). Lines marking other anchors are
+ * dropped from whatever gets included.
+ */
+const mdbook: IncludeSyntax = {
+  directive: /(?<!\\)\{\{\s*#(?:include|rustdoc_include)\s+([^}\s]+)\s*\}\}/g,
+  target(from, [, spec]) {
+    return resolve(dirname(from), spec.split(':')[0])
+  },
+  select(text, [, spec]) {
+    const [, ...parts] = spec.split(':')
+    let lines = text.split('\n')
+    if (parts.length === 1 && parts[0] && !/^\d+$/.test(parts[0])) {
+      const name = parts[0]
+      const start = lines.findIndex((l) => new RegExp(`\\bANCHOR:\\s*${name}\\b`).test(l))
+      const end = lines.findIndex((l) => new RegExp(`\\bANCHOR_END:\\s*${name}\\b`).test(l))
+      lines = start === -1 ? [] : lines.slice(start + 1, end === -1 ? undefined : end)
+    } else if (parts.length === 1 && parts[0]) {
+      lines = lines.filter(lineRanges(parts[0]))
+    } else if (parts.length === 2) {
+      lines = lines.filter(lineRanges(`${parts[0] || 1}..${parts[1] || -1}`))
+    }
+    return lines.filter((l) => !anchorLine.test(l)).join('\n')
+  },
+  // mdBook fails the build instead, but we'd rather index the rest of the page
+  unresolved: (_from, [directive]) => directive,
+}
+
+/////////////////////////////
+// EXPANSION
+/////////////////////////////
+
+/** Include syntax for each format that has one, keyed like `formats` */
+const includeSyntaxes: Record<string, IncludeSyntax> = { asciidoc, markdown: mdbook }
+
+async function readIncluded(path: string) {
+  try {
+    return await Deno.readTextFile(path)
+  } catch {
+    return undefined
+  }
+}
+
+const isWithin = (root: string, path: string) => {
+  const rel = relative(root, path)
+  return !/^\.\.([/\\]|$)/.test(rel) && !isAbsolute(rel)
+}
+
+/**
+ * Whether a file is inside the corpus, once symlinks are followed. Files that
+ * don't exist count, since there's nothing to leak.
+ */
+async function inCorpus(root: string, path: string) {
+  if (!isWithin(root, path)) return false
+  try {
+    return isWithin(await Deno.realPath(root), await Deno.realPath(path))
+  } catch {
+    return true
+  }
+}
+
+/**
+ * Expand include directives in `text`, which was read from `path`. Also
+ * returns the absolute path of every file it tried to include, found or not,
+ * so callers can tell when the result is out of date. A file that includes
+ * itself, directly or not, gets the unresolved treatment the second time, as
+ * does anything outside `root`, so a corpus can't pull in `~/.ssh` or `.env`
+ * files from elsewhere and have them sent to the model.
+ */
+export async function expandIncludes(
+  path: string,
+  format: string,
+  text: string,
+  root: string,
+) {
+  const syntax = includeSyntaxes[format]
+  const includes = new Set<string>()
+  if (!syntax) return { text, includes: [] }
+  root = resolve(root)
+
+  const expand = async (text: string, stack: string[]): Promise<string> => {
+    const from = stack.at(-1)!
+    const parts: string[] = []
+    let last = 0
+    for (const match of text.matchAll(syntax.directive)) {
+      parts.push(text.slice(last, match.index))
+      last = match.index + match[0].length
+      const target = syntax.target(from, match)
+      if (!target || stack.includes(target) || !(await inCorpus(root, target))) {
+        parts.push(syntax.unresolved(from, match))
+        continue
+      }
+      includes.add(target)
+      // the directive's own line break stays, so drop the file's final one
+      const included = (await readIncluded(target))?.replace(/\r?\n$/, '')
+      if (included === undefined) {
+        parts.push(syntax.unresolved(from, match))
+        continue
+      }
+      const expanded = await expand(syntax.select(included, match), [...stack, target])
+      parts.push(syntax.adjust?.(expanded, match) ?? expanded)
+    }
+    parts.push(text.slice(last))
+    return parts.join('')
+  }
+
+  return { text: await expand(text, [resolve(path)]), includes: [...includes] }
+}
@@ -0,0 +1,66 @@
+import { assertEquals } from 'jsr:@std/assert@1'
+import { join } from 'jsr:@std/path@1.0'
+import { expandIncludes, lineRanges, selectTags } from './includes.ts'
+
+const lines = ['one', 'two', 'three', 'four', 'five']
+
+Deno.test('lineRanges takes 1-based inclusive ranges', () => {
+  assertEquals(lines.filter(lineRanges('2..3;5')), ['two', 'three', 'five'])
+  assertEquals(lines.filter(lineRanges('4..-1')), ['four', 'five'])
+  assertEquals(lines.filter(lineRanges('4..')), ['four', 'five'])
+})
+
+Deno.test('selectTags keeps, excludes, and wildcards tagged regions', () => {
+  const text = [
+    'untagged',
+    '// tag::a[]',
+    'in a',
+    '// tag::b[]',
+    'in a and b',
+    '// end::b[]',
+    '// end::a[]',
+    '// tag::c[]',
+    'in c',
+    '// end::c[]',
+  ]
+  assertEquals(selectTags(text, 'b'), ['in a and b'])
+  assertEquals(selectTags(text, 'a'), ['in a', 'in a and b'])
+  assertEquals(selectTags(text, '!b'), ['untagged', 'in a', 'in c'])
+  assertEquals(selectTags(text, '*'), ['in a', 'in a and b', 'in c'])
+  assertEquals(selectTags(text, '**'), ['untagged', 'in a', 'in a and b', 'in c'])
+})
+
+Deno.test('expandIncludes follows directives within the corpus only', async () => {
+  const outside = await Deno.makeTempDir()
+  const root = join(outside, 'corpus')
+  await Deno.mkdir(join(root, 'src'), { recursive: true })
+  await Deno.writeTextFile(join(outside, 'secret.env'), 'API_KEY=hunter2\n')
+  await Deno.writeTextFile(
+    join(root, 'src', 'main.rs'),
+    'use x;\n// ANCHOR: body\nfn main() {}\n// ANCHOR_END: body\n',
+  )
+  const page = join(root, 'src', 'page.md')
+  const text = '{{#include main.rs:body}}\n{{#include ../../secret.env}}\n'
+  try {
+    const result = await expandIncludes(page, 'markdown', text, root)
+    assertEquals(result.text, 'fn main() {}\n{{#include ../../secret.env}}\n')
+    assertEquals(result.includes, [join(root, 'src', 'main.rs')])
+  } finally {
+    await Deno.remove(outside, { recursive: true })
+  }
+})
+
+Deno.test('expandIncludes shifts AsciiDoc levels and notes missing files', async () => {
+  const root = await Deno.makeTempDir()
+  await Deno.writeTextFile(join(root, 'part.adoc'), '= Part\n\ntext\n')
+  const text = 'include::part.adoc[leveloffset=+1]\ninclude::nope.adoc[]\n'
+  try {
+    const result = await expandIncludes(join(root, 'book.adoc'), 'asciidoc', text, root)
+    assertEquals(
+      result.text,
+      '== Part\n\ntext\nUnresolved directive in book.adoc - include::nope.adoc[]\n',
+    )
+  } finally {
+    await Deno.remove(root, { recursive: true })
+  }
+})
@@ -0,0 +1,520 @@
+import $ from 'jsr:@david/dax@0.42.0'
+import {
+  type GenerateContentResponseUsageMetadata,
+  GoogleGenAI,
+  type Schema,
+  type Type,
+} from 'npm:@google/genai@0.14.0'
+
+export interface Message {
+  role: 'user' | 'assistant'
+  content: string
+}
+
+/**
+ * A chunk of the system prompt. Backends with explicit prompt caching put a
+ * cache breakpoint after blocks marked `cache`; the rest just concatenate.
+ */
+export interface SystemBlock {
+  text: string
+  cache?: boolean
+}
+
+/** The subset of JSON Schema we use to ask for structured output */
+export interface JsonSchema {
+  type: 'object' | 'array' | 'string' | 'number' | 'integer' | 'boolean'
+  description?: string
+  properties?: Record<string, JsonSchema>
+  required?: string[]
+  items?: JsonSchema
+}
+
+export interface LlmRequest {
+  model: string
+  system: string | SystemBlock[]
+  messages: Message[]
+  /**
+   * Ask for JSON matching this schema. Backends that can constrain their
+   * output to a schema do; the rest only have the prompt to go on, so check
+   * the result with `validateJson` either way.
+   */
+  schema?: JsonSchema
+}
+
+export interface Usage {
+  input: number
+  output: number
+  cacheRead?: number
+  cacheWrite?: number
+}
+
+export interface Completion {
+  text: string
+  usage?: Usage
+}
+
+/**
+ * An LLM backend. `stream` calls `onText` with each chunk as it arrives and
+ * resolves to the same thing `complete` would.
+ */
+export interface Provider {
+  name: string
+  defaultModel: string
+  /** Short names for models, which get expanded before calling the API */
+  aliases?: Record<string, string>
+  complete(req: LlmRequest): Promise<Completion>
+  stream(req: LlmRequest, onText: (text: string) => void): Promise<Completion>
+  /** Exact input token count, for backends that can tell us */
+  countTokens?(req: LlmRequest): Promise<number>
+}
+
+export interface ProviderOptions {
+  apiKey?: string
+  /** Mostly useful for pointing at a local mock server */
+  baseUrl?: string
+}
+
+const systemText = (system: string | SystemBlock[]) =>
+  typeof system === 'string' ? system : system.map((b) => b.text).join('\n\n')
+
+/** Check `value` against `schema`, returning a description of the first problem */
+export function validateJson(
+  value: unknown,
+  schema: JsonSchema,
+  at = 'response',
+): string | undefined {
+  const actual = Array.isArray(value) ? 'array' : value === null ? 'null' : typeof value
+  const expected = schema.type === 'integer' ? 'number' : schema.type
+  if (actual !== expected || (schema.type === 'integer' && !Number.isInteger(value))) {
+    const article = /^[aeiou]/.test(schema.type) ? 'an' : 'a'
+    return `${at} should be ${article} ${schema.type}, not ${actual}`
+  }
+  if (Array.isArray(value) && schema.items) {
+    for (const [i, item] of value.entries()) {
+      const error = validateJson(item, schema.items, `${at}[${i}]`)
+      if (error) return error
+    }
+  }
+  if (schema.type === 'object') {
+    const obj = value as Record<string, unknown>
+    const missing = schema.required?.find((key) => !(key in obj))
+    if (missing) return `${at} is missing "${missing}"`
+    for (const [key, prop] of Object.entries(schema.properties ?? {})) {
+      const error = key in obj ? validateJson(obj[key], prop, `${at}.${key}`) : undefined
+      if (error) return error
+    }
+  }
+}
+
+/** Full name of a model, for looking up things like prices */
+export const modelName = (provider: Provider, model: string) =>
+  provider.aliases?.[model] ?? model
+
+/** Rough, but close enough for English prose and markup with most tokenizers */
+export const estimateTokens = (text: string) => Math.ceil(text.length / 4)
+
+/** Input tokens for a request: exact if the provider can count, estimated otherwise */
+export async function countTokens(provider: Provider, req: LlmRequest) {
+  if (provider.countTokens) return { tokens: await provider.countTokens(req), exact: true }
+  const text = [systemText(req.system), ...req.messages.map((m) => m.content)].join('\n\n')
+  return { tokens: estimateTokens(text), exact: false }
+}
+
+/////////////////////////////
+// AI CLI
+/////////////////////////////
+
+// The ai CLI only takes a single prompt, so earlier turns get inlined as a transcript
+function cliPrompt(messages: Message[]) {
+  if (messages.length === 1) return messages[0].content
+  return messages.map((m) => `<${m.role}>\n${m.content}\n</${m.role}>`).join('\n\n')
+}
+
+const aiCommand = ({ model, system, messages }: LlmRequest) =>
+  $`ai -m ${model} -s ${systemText(system)} ${cliPrompt(messages)} --raw`
+
+/**
+ * Shells out to the `ai` CLI, which has to be on the PATH. Model aliases are
+ * resolved by the CLI.
+ */
+export const aiCli: Provider = {
+  name: 'ai',
+  defaultModel: 'flash',
+  async complete(req) {
+    return { text: await aiCommand(req).text() }
+  },
+  async stream(req, onText) {
+    const child = aiCommand(req).stdout('piped').spawn()
+    let text = ''
+    for await (const chunk of child.stdout().pipeThrough(new TextDecoderStream())) {
+      text += chunk
+      onText(chunk)
+    }
+    await child
+    return { text }
+  },
+}
+
+/////////////////////////////
+// GEMINI
+/////////////////////////////
+
+const geminiModels: Record<string, string> = {
+  flash: 'gemini-2.5-flash',
+  'flash-lite': 'gemini-2.5-flash-lite',
+  pro: 'gemini-2.5-pro',
+}
+
+/** Gemini takes OpenAPI-style schemas, which spell types in uppercase */
+function geminiSchema({ type, properties, items, ...rest }: JsonSchema): Schema {
+  return {
+    ...rest,
+    type: type.toUpperCase() as Type,
+    ...(properties && {
+      properties: Object.fromEntries(
+        Object.entries(properties).map(([key, prop]) => [key, geminiSchema(prop)]),
+      ),
+    }),
+    ...(items && { items: geminiSchema(items) }),
+  }
+}
+
+type GeminiUsage = GenerateContentResponseUsageMetadata
+
+/** Gemini counts cached tokens as part of the prompt, and thinking as separate */
+const geminiUsage = (u: GeminiUsage | undefined): Usage | undefined =>
+  u && {
+    input: (u.promptTokenCount ?? 0) - (u.cachedContentTokenCount ?? 0),
+    output: (u.candidatesTokenCount ?? 0) + (u.thoughtsTokenCount ?? 0),
+    cacheRead: u.cachedContentTokenCount ?? 0,
+  }
+
+function gemini({ apiKey, baseUrl }: ProviderOptions): Provider {
+  apiKey ??= Deno.env.get('GEMINI_API_KEY')
+  if (!apiKey) throw new Error('GEMINI_API_KEY is not set')
+  baseUrl ??= Deno.env.get('GEMINI_BASE_URL')
+  const client = new GoogleGenAI({ apiKey, httpOptions: baseUrl ? { baseUrl } : undefined })
+
+  const params = ({ model, system, messages, schema }: LlmRequest) => ({
+    model: geminiModels[model] ?? model,
+    contents: messages.map((m) => ({
+      role: m.role === 'assistant' ? 'model' : 'user',
+      parts: [{ text: m.content }],
+    })),
+    config: {
+      systemInstruction: systemText(system),
+      ...(schema && {
+        responseMimeType: 'application/json',
+        responseSchema: geminiSchema(schema),
+      }),
+    },
+  })
+
+  return {
+    name: 'gemini',
+    defaultModel: 'flash',
+    aliases: geminiModels,
+    async complete(req) {
+      const response = await client.models.generateContent(params(req))
+      return { text: response.text ?? '', usage: geminiUsage(response.usageMetadata) }
+    },
+    async stream(req, onText) {
+      let text = ''
+      let usage: GeminiUsage | undefined
+      for await (const chunk of await client.models.generateContentStream(params(req))) {
+        // each chunk has the usage so far
+        usage = chunk.usageMetadata ?? usage
+        if (!chunk.text) continue
+        text += chunk.text
+        onText(chunk.text)
+      }
+      return { text, usage: geminiUsage(usage) }
+    },
+    async countTokens(req) {
+      // the Gemini API doesn't take a system instruction when counting, so
+      // count it as part of the contents instead
+      const { model, contents } = params(req)
+      const system = { role: 'user', parts: [{ text: systemText(req.system) }] }
+      const response = await client.models.countTokens({
+        model,
+        contents: [system, ...contents],
+      })
+      return response.totalTokens ?? 0
+    },
+  }
+}
+
+/////////////////////////////
+// OPENAI-COMPATIBLE
+/////////////////////////////
+
+async function post(url: string, headers: Record<string, string>, body: unknown) {
+  const response = await fetch(url, {
+    method: 'POST',
+    headers: { 'content-type': 'application/json', ...headers },
+    body: JSON.stringify(body),
+  })
+  if (!response.ok) {
+    const detail = await response.text()
+    throw new Error(`Request to ${url} failed with ${response.status}: ${detail}`)
+  }
+  return response
+}
+
+/** Pull the data payloads out of a server-sent events stream */
+async function* sseData(body: ReadableStream<Uint8Array>) {
+  let buffer = ''
+  for await (const chunk of body.pipeThrough(new TextDecoderStream())) {
+    buffer += chunk
+    const events = buffer.split(/\r?\n\r?\n/)
+    buffer = events.pop()!
+    for (const event of events) {
+      const data = event.split(/\r?\n/)
+        .filter((line) => line.startsWith('data:'))
+        .map((line) => line.slice(5).trimStart())
+        .join('\n')
+      if (data) yield data
+    }
+  }
+}
+
+interface OpenaiUsage {
+  prompt_tokens: number
+  completion_tokens: number
+  prompt_tokens_details?: { cached_tokens?: number }
+}
+
+/** Cached tokens are counted as part of the prompt */
+function openaiUsage(u: OpenaiUsage): Usage {
+  const cached = u.prompt_tokens_details?.cached_tokens ?? 0
+  return { input: u.prompt_tokens - cached, output: u.completion_tokens, cacheRead: cached }
+}
+
+/**
+ * Strict mode wants every property required and nothing else allowed. Servers
+ * without strict mode (llama.cpp turns the schema into a grammar) don't mind.
+ */
+function openaiSchema(schema: JsonSchema): Record<string, unknown> {
+  const { properties, items } = schema
+  return {
+    ...schema,
+    ...(properties && {
+      properties: Object.fromEntries(
+        Object.entries(properties).map(([key, prop]) => [key, openaiSchema(prop)]),
+      ),
+      required: Object.keys(properties),
+      additionalProperties: false,
+    }),
+    ...(items && { items: openaiSchema(items) }),
+  }
+}
+
+/**
+ * Anything that speaks `/v1/chat/completions`: llama.cpp, vLLM, Ollama, or
+ * OpenAI itself. Defaults to llama.cpp's default address. The model name is
+ * passed through as-is, which local servers serving a single model ignore.
+ */
+function openai({ apiKey, baseUrl }: ProviderOptions): Provider {
+  apiKey ??= Deno.env.get('OPENAI_API_KEY')
+  baseUrl ??= Deno.env.get('OPENAI_BASE_URL') ?? 'http://localhost:8080/v1'
+  const url = `${baseUrl.replace(/\/$/, '')}/chat/completions`
+  const headers: Record<string, string> = {}
+  if (apiKey) headers.authorization = `Bearer ${apiKey}`
+
+  const body = ({ model, system, messages, schema }: LlmRequest, stream: boolean) => ({
+    model,
+    stream,
+    ...(stream && { stream_options: { include_usage: true } }),
+    messages: [{ role: 'system', content: systemText(system) }, ...messages],
+    ...(schema && {
+      response_format: {
+        type: 'json_schema',
+        json_schema: { name: 'response', strict: true, schema: openaiSchema(schema) },
+      },
+    }),
+  })
+
+  return {
+    name: 'openai',
+    defaultModel: 'gpt-4.1-mini',
+    async complete(req) {
+      const response = await post(url, headers, body(req, false))
+      const json = await response.json()
+      const text = json.choices[0].message.content ?? ''
+      return { text, usage: json.usage && openaiUsage(json.usage) }
+    },
+    async stream(req, onText) {
+      const response = await post(url, headers, body(req, true))
+      let text = ''
+      let usage: Usage | undefined
+      for await (const data of sseData(response.body!)) {
+        if (data === '[DONE]') break
+        const chunk = JSON.parse(data)
+        // with include_usage, the last chunk has the usage and no choices
+        if (chunk.usage) usage = openaiUsage(chunk.usage)
+        const delta: string | undefined = chunk.choices[0]?.delta?.content
+        if (!delta) continue
+        text += delta
+        onText(delta)
+      }
+      return { text, usage }
+    },
+  }
+}
+
+/////////////////////////////
+// ANTHROPIC
+/////////////////////////////
+
+const anthropicModels: Record<string, string> = {
+  haiku: 'claude-haiku-4-5',
+  sonnet: 'claude-sonnet-4-5',
+  opus: 'claude-opus-4-1',
+}
+
+// The API rejects requests with more than this many cache_control blocks
+const MAX_CACHE_BREAKPOINTS = 4
+
+// There's no JSON mode, so structured output comes from forcing a call to this tool
+const RESPOND_TOOL = 'respond'
+
+interface AnthropicUsage {
+  input_tokens: number
+  output_tokens: number
+  cache_read_input_tokens?: number
+  cache_creation_input_tokens?: number
+}
+
+const anthropicUsage = (u: AnthropicUsage): Usage => ({
+  input: u.input_tokens,
+  output: u.output_tokens,
+  cacheRead: u.cache_read_input_tokens ?? 0,
+  cacheWrite: u.cache_creation_input_tokens ?? 0,
+})
+
+/**
+ * Anthropic Messages API. Each system block marked `cache` gets a cache
+ * breakpoint, so a request whose leading blocks match an earlier one only
+ * pays the cache read price for them. If there are more cacheable blocks than
+ * breakpoints allowed, the last ones win because each breakpoint covers the
+ * whole prefix before it. With a schema, the response is the input of a tool
+ * call the model is made to do.
+ */
+function anthropic({ apiKey, baseUrl }: ProviderOptions): Provider {
+  apiKey ??= Deno.env.get('ANTHROPIC_API_KEY')
+  if (!apiKey) throw new Error('ANTHROPIC_API_KEY is not set')
+  baseUrl ??= Deno.env.get('ANTHROPIC_BASE_URL') ?? 'https://api.anthropic.com'
+  const url = `${baseUrl.replace(/\/$/, '')}/v1/messages`
+  const countUrl = `${url}/count_tokens`
+  const headers = { 'x-api-key': apiKey, 'anthropic-version': '2023-06-01' }
+
+  const body = ({ model, system, messages, schema }: LlmRequest, stream: boolean) => {
+    const blocks = (typeof system === 'string' ? [{ text: system }] : system)
+      .filter((b) => b.text)
+    const breakpoints = blocks.filter((b) => b.cache).slice(-MAX_CACHE_BREAKPOINTS)
+    return {
+      model: anthropicModels[model] ?? model,
+      max_tokens: 8192,
+      stream,
+      system: blocks.map((b) => ({
+        type: 'text',
+        text: b.text,
+        ...(breakpoints.includes(b) ? { cache_control: { type: 'ephemeral' } } : {}),
+      })),
+      messages,
+      ...(schema && {
+        tools: [
+          { name: RESPOND_TOOL, description: 'Give your response', input_schema: schema },
+        ],
+        tool_choice: { type: 'tool', name: RESPOND_TOOL },
+      }),
+    }
+  }
+
+  return {
+    name: 'anthropic',
+    defaultModel: 'sonnet',
+    aliases: anthropicModels,
+    async complete(req) {
+      const response = await post(url, headers, body(req, false))
+      const json = await response.json()
+      const text = json.content
+        .map((b: { type: string; text?: string; input?: unknown }) =>
+          b.type === 'tool_use' ? JSON.stringify(b.input) : b.text ?? ''
+        )
+        .join('')
+      return { text, usage: anthropicUsage(json.usage) }
+    },
+    async stream(req, onText) {
+      const response = await post(url, headers, body(req, true))
+      let text = ''
+      let usage: AnthropicUsage | undefined
+      for await (const data of sseData(response.body!)) {
+        const event = JSON.parse(data)
+        if (event.type === 'message_start') {
+          usage = event.message.usage
+        } else if (event.type === 'message_delta' && usage) {
+          usage.output_tokens = event.usage.output_tokens
+        } else if (event.type === 'content_block_delta') {
+          // tool calls stream their input as JSON
+          const chunk: string | undefined = event.delta.text ?? event.delta.partial_json
+          if (!chunk) continue
+          text += chunk
+          onText(chunk)
+        } else if (event.type === 'error') {
+          throw new Error(`Anthropic stream error: ${event.error.message}`)
+        }
+      }
+      return { text, usage: usage && anthropicUsage(usage) }
+    },
+    async countTokens(req) {
+      const { model, system, messages } = body(req, false)
+      const response = await post(countUrl, headers, { model, system, messages })
+      const json: { input_tokens: number } = await response.json()
+      return json.input_tokens
+    },
+  }
+}
+
+/////////////////////////////
+// PRICING
+/////////////////////////////
+
+/** Dollars per million tokens. Cache prices default to the input price. */
+export interface Price {
+  input: number
+  output: number
+  cacheRead?: number
+  cacheWrite?: number
+}
+
+/**
+ * List prices at the time of writing, for prompts under any long-context
+ * surcharge. Override or add to them in the config file.
+ */
+export const defaultPrices: Record<string, Price> = {
+  'gemini-2.5-flash': { input: 0.3, output: 2.5, cacheRead: 0.075 },
+  'gemini-2.5-flash-lite': { input: 0.1, output: 0.4, cacheRead: 0.025 },
+  'gemini-2.5-pro': { input: 1.25, output: 10, cacheRead: 0.31 },
+  'claude-haiku-4-5': { input: 1, output: 5, cacheRead: 0.1, cacheWrite: 1.25 },
+  'claude-sonnet-4-5': { input: 3, output: 15, cacheRead: 0.3, cacheWrite: 3.75 },
+  'claude-opus-4-1': { input: 15, output: 75, cacheRead: 1.5, cacheWrite: 18.75 },
+  'gpt-4.1-mini': { input: 0.4, output: 1.6, cacheRead: 0.1 },
+}
+
+/** Estimated cost of a call in dollars */
+export function cost(usage: Usage, price: Price) {
+  const { input, output, cacheRead = 0, cacheWrite = 0 } = usage
+  const total = input * price.input + output * price.output +
+    cacheRead * (price.cacheRead ?? price.input) +
+    cacheWrite * (price.cacheWrite ?? price.input)
+  return total / 1_000_000
+}
+
+/** Providers are constructed lazily so we only complain about the API key we need */
+export const providers: Record<string, (opts: ProviderOptions) => Provider> = {
+  gemini,
+  openai,
+  anthropic,
+  ai: () => aiCli,
+}
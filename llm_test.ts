@@ -0,0 +1,312 @@
+import {
+  assert,
+  assertAlmostEquals,
+  assertEquals,
+  assertObjectMatch,
+  assertRejects,
+} from 'jsr:@std/assert@1'
+import { cost, type JsonSchema, type LlmRequest, providers, validateJson } from './llm.ts'
+
+const paths: JsonSchema = {
+  type: 'object',
+  properties: { paths: { type: 'array', items: { type: 'string' } } },
+  required: ['paths'],
+}
+
+Deno.test('validateJson accepts matching values', () => {
+  assertEquals(validateJson({ paths: ['a.md', 'b.md'] }, paths), undefined)
+  assertEquals(validateJson({ paths: [], extra: 1 }, paths), undefined)
+  assertEquals(validateJson(3, { type: 'integer' }), undefined)
+})
+
+Deno.test('validateJson says where a value goes wrong', () => {
+  assertEquals(validateJson(['a.md'], paths), 'response should be an object, not array')
+  assertEquals(validateJson({}, paths), 'response is missing "paths"')
+  assertEquals(
+    validateJson({ paths: ['a.md', 2] }, paths),
+    'response.paths[1] should be a string, not number',
+  )
+  assertEquals(validateJson(null, paths), 'response should be an object, not null')
+  assertEquals(
+    validateJson(1.5, { type: 'integer' }),
+    'response should be an integer, not number',
+  )
+})
+
+Deno.test('cost prices cache reads and writes, defaulting to the input price', () => {
+  const usage = { input: 1_000_000, output: 100_000, cacheRead: 2_000_000, cacheWrite: 0 }
+  assertAlmostEquals(cost(usage, { input: 0.3, output: 2.5, cacheRead: 0.075 }), 0.7)
+  const writes = { input: 0, output: 0, cacheWrite: 1_000_000 }
+  assertAlmostEquals(cost(writes, { input: 1, output: 5 }), 1)
+})
+
+/////////////////////////////
+// PROVIDERS
+/////////////////////////////
+
+const request: LlmRequest = {
+  model: 'test-model',
+  system: [{ text: 'Docs', cache: true }, { text: 'Answer' }],
+  messages: [
+    { role: 'user', content: 'Hi' },
+    { role: 'assistant', content: 'Hello' },
+    { role: 'user', content: 'More?' },
+  ],
+}
+
+interface Received {
+  path: string
+  headers: Headers
+  body: Record<string, unknown>
+}
+
+/**
+ * Serve `responses` in order on a local port while `f` runs, the way a
+ * provider's API would, and return the requests that came in
+ */
+async function withServer(
+  responses: Response[],
+  f: (baseUrl: string) => Promise<void>,
+): Promise<Received[]> {
+  const received: Received[] = []
+  const options = { hostname: '127.0.0.1', port: 0, onListen: () => {} }
+  const server = Deno.serve(options, async (req) => {
+    const path = new URL(req.url).pathname
+    received.push({ path, headers: req.headers, body: await req.json() })
+    return responses.shift() ?? new Response('no more responses', { status: 500 })
+  })
+  try {
+    await f(`http://127.0.0.1:${server.addr.port}`)
+  } finally {
+    await server.shutdown()
+  }
+  return received
+}
+
+/** A server-sent events response, sent a few bytes at a time to test buffering */
+function sse(events: string[]) {
+  const bytes = new TextEncoder().encode(events.map((e) => `${e}\n\n`).join(''))
+  const body = new ReadableStream<Uint8Array>({
+    start(controller) {
+      for (let i = 0; i < bytes.length; i += 7) controller.enqueue(bytes.slice(i, i + 7))
+      controller.close()
+    },
+  })
+  return new Response(body, { headers: { 'content-type': 'text/event-stream' } })
+}
+
+const data = (value: unknown) => `data: ${JSON.stringify(value)}`
+
+Deno.test('gemini goes to GEMINI_BASE_URL with the full model name', async () => {
+  const usageMetadata = {
+    promptTokenCount: 50,
+    cachedContentTokenCount: 10,
+    candidatesTokenCount: 5,
+    thoughtsTokenCount: 3,
+  }
+  const reply = (text: string) =>
+    ({ candidates: [{ content: { role: 'model', parts: [{ text }] } }], usageMetadata })
+  const usage = { input: 40, output: 8, cacheRead: 10 }
+  const previous = Deno.env.get('GEMINI_BASE_URL')
+  const received = await withServer([
+    Response.json(reply('Hello')),
+    sse([data(reply('Hel')), data(reply('lo'))]),
+    Response.json({ totalTokens: 123 }),
+  ], async (baseUrl) => {
+    Deno.env.set('GEMINI_BASE_URL', baseUrl)
+    try {
+      const gemini = providers.gemini({ apiKey: 'test-key' })
+      const req = { ...request, model: 'flash' }
+      assertEquals(await gemini.complete(req), { text: 'Hello', usage })
+      const chunks: string[] = []
+      const streamed = await gemini.stream(req, (t) => chunks.push(t))
+      assertEquals(streamed, { text: 'Hello', usage })
+      assertEquals(chunks, ['Hel', 'lo'])
+      assertEquals(await gemini.countTokens!(req), 123)
+    } finally {
+      if (previous === undefined) Deno.env.delete('GEMINI_BASE_URL')
+      else Deno.env.set('GEMINI_BASE_URL', previous)
+    }
+  })
+
+  const model = '/models/gemini-2.5-flash'
+  assert(received[0].path.endsWith(`${model}:generateContent`))
+  assert(received[1].path.endsWith(`${model}:streamGenerateContent`))
+  assert(received[2].path.endsWith(`${model}:countTokens`))
+  assertEquals(received[0].headers.get('x-goog-api-key'), 'test-key')
+  assertEquals(received[0].body.contents, [
+    { role: 'user', parts: [{ text: 'Hi' }] },
+    { role: 'model', parts: [{ text: 'Hello' }] },
+    { role: 'user', parts: [{ text: 'More?' }] },
+  ])
+  assert(JSON.stringify(received[0].body.systemInstruction).includes('Docs\\n\\nAnswer'))
+})
+
+Deno.test('openai streams deltas and takes usage from the last chunk', async () => {
+  const received = await withServer([
+    sse([
+      data({ choices: [{ delta: { role: 'assistant', content: '' } }] }),
+      data({ choices: [{ delta: { content: 'Hel' } }] }),
+      data({ choices: [{ delta: { content: 'lo' } }] }),
+      data({
+        choices: [],
+        usage: {
+          prompt_tokens: 10,
+          completion_tokens: 2,
+          prompt_tokens_details: { cached_tokens: 4 },
+        },
+      }),
+      'data: [DONE]',
+    ]),
+  ], async (baseUrl) => {
+    const openai = providers.openai({ baseUrl: `${baseUrl}/v1`, apiKey: 'test-key' })
+    const chunks: string[] = []
+    const completion = await openai.stream(request, (t) => chunks.push(t))
+    assertEquals(chunks, ['Hel', 'lo'])
+    const usage = { input: 6, output: 2, cacheRead: 4 }
+    assertEquals(completion, { text: 'Hello', usage })
+  })
+
+  const [{ path, headers, body }] = received
+  assertEquals(path, '/v1/chat/completions')
+  assertEquals(headers.get('authorization'), 'Bearer test-key')
+  assertObjectMatch(body, {
+    model: 'test-model',
+    stream: true,
+    stream_options: { include_usage: true },
+  })
+  assertEquals(body.messages, [
+    { role: 'system', content: 'Docs\n\nAnswer' },
+    ...request.messages,
+  ])
+})
+
+Deno.test('openai asks for strict JSON and reports HTTP errors', async () => {
+  const received = await withServer([
+    Response.json({
+      choices: [{ message: { content: '{"paths": []}' } }],
+      usage: { prompt_tokens: 5, completion_tokens: 1 },
+    }),
+    new Response('slow down', { status: 429 }),
+  ], async (baseUrl) => {
+    const openai = providers.openai({ baseUrl: `${baseUrl}/v1` })
+    assertEquals(await openai.complete({ ...request, schema: paths }), {
+      text: '{"paths": []}',
+      usage: { input: 5, output: 1, cacheRead: 0 },
+    })
+    await assertRejects(() => openai.complete(request), Error, 'failed with 429: slow down')
+  })
+
+  assertEquals(received[0].headers.get('authorization'), null)
+  assertEquals(received[0].body.response_format, {
+    type: 'json_schema',
+    json_schema: {
+      name: 'response',
+      strict: true,
+      schema: {
+        type: 'object',
+        properties: { paths: { type: 'array', items: { type: 'string' } } },
+        required: ['paths'],
+        additionalProperties: false,
+      },
+    },
+  })
+})
+
+/** An Anthropic stream event, with the `event:` line the API sends before each one */
+const event = (value: { type: string }) => `event: ${value.type}\n${data(value)}`
+
+Deno.test('anthropic streams text and fills in usage from message_delta', async () => {
+  const received = await withServer([
+    sse([
+      event({
+        type: 'message_start',
+        message: {
+          usage: {
+            input_tokens: 20,
+            output_tokens: 1,
+            cache_read_input_tokens: 100,
+            cache_creation_input_tokens: 0,
+          },
+        },
+      }),
+      event({ type: 'content_block_start', index: 0, content_block: { type: 'text' } }),
+      event({ type: 'ping' }),
+      event({ type: 'content_block_delta', delta: { type: 'text_delta', text: 'Hi' } }),
+      event({ type: 'content_block_delta', delta: { type: 'text_delta', text: ' there' } }),
+      event({ type: 'content_block_stop', index: 0 }),
+      event({ type: 'message_delta', delta: {}, usage: { output_tokens: 5 } }),
+      event({ type: 'message_stop' }),
+    ]),
+  ], async (baseUrl) => {
+    const anthropic = providers.anthropic({ baseUrl, apiKey: 'test-key' })
+    const chunks: string[] = []
+    const completion = await anthropic.stream(
+      { ...request, model: 'sonnet' },
+      (t) => chunks.push(t),
+    )
+    assertEquals(chunks, ['Hi', ' there'])
+    assertEquals(completion, {
+      text: 'Hi there',
+      usage: { input: 20, output: 5, cacheRead: 100, cacheWrite: 0 },
+    })
+  })
+
+  const [{ path, headers, body }] = received
+  assertEquals(path, '/v1/messages')
+  assertEquals(headers.get('x-api-key'), 'test-key')
+  assertEquals(headers.get('anthropic-version'), '2023-06-01')
+  assertObjectMatch(body, { model: 'claude-sonnet-4-5', stream: true })
+  assertEquals(body.system, [
+    { type: 'text', text: 'Docs', cache_control: { type: 'ephemeral' } },
+    { type: 'text', text: 'Answer' },
+  ])
+})
+
+Deno.test('anthropic returns the forced tool call input as the response', async () => {
+  const input = { paths: ['a.md'] }
+  const toolUse = { type: 'tool_use', id: 'toolu_1', name: 'respond', input }
+  const received = await withServer([
+    Response.json({ content: [toolUse], usage: { input_tokens: 3, output_tokens: 4 } }),
+    sse([
+      event({
+        type: 'message_start',
+        message: { usage: { input_tokens: 3, output_tokens: 1 } },
+      }),
+      event({
+        type: 'content_block_delta',
+        delta: { type: 'input_json_delta', partial_json: '{"paths": ' },
+      }),
+      event({
+        type: 'content_block_delta',
+        delta: { type: 'input_json_delta', partial_json: '["a.md"]}' },
+      }),
+      event({ type: 'message_delta', delta: {}, usage: { output_tokens: 4 } }),
+    ]),
+  ], async (baseUrl) => {
+    const anthropic = providers.anthropic({ baseUrl, apiKey: 'test-key' })
+    const req = { ...request, schema: paths }
+    const usage = { input: 3, output: 4, cacheRead: 0, cacheWrite: 0 }
+    assertEquals(await anthropic.complete(req), { text: '{"paths":["a.md"]}', usage })
+    const streamed = await anthropic.stream(req, () => {})
+    assertEquals(streamed, { text: '{"paths": ["a.md"]}', usage })
+  })
+
+  assertObjectMatch(received[0].body, {
+    tools: [{ name: 'respond', input_schema: paths }],
+    tool_choice: { type: 'tool', name: 'respond' },
+  })
+})
+
+Deno.test('anthropic fails on an error event mid-stream', async () => {
+  const error = { type: 'overloaded_error', message: 'Overloaded' }
+  const overloaded = { type: 'error', error }
+  await withServer([sse([event(overloaded)])], async (baseUrl) => {
+    const anthropic = providers.anthropic({ baseUrl, apiKey: 'test-key' })
+    await assertRejects(
+      () => anthropic.stream(request, () => {}),
+      Error,
+      'Anthropic stream error: Overloaded',
+    )
+  })
+})
@@ -1,82 +1,947 @@
-#! /usr/bin/env -S deno run --allow-read --allow-env --allow-net --allow-run=glow,ai
+#! /usr/bin/env -S deno run --allow-read --allow-write --allow-env --allow-net --allow-run=glow,ai,deno
 
-import { relative } from 'jsr:@std/path@1.0'
-import { walk } from 'jsr:@std/fs@1.0/walk'
-import { Command, ValidationError } from 'jsr:@cliffy/command@1.0.0-rc.7'
+import { basename, dirname, globToRegExp, join, resolve } from 'jsr:@std/path@1.0'
+import { parse as parseToml } from 'jsr:@std/toml@1'
+import { parse as parseYaml } from 'jsr:@std/yaml@1'
+import {
+  type ArgumentValue,
+  Command,
+  EnumType,
+  ValidationError,
+} from 'jsr:@cliffy/command@1.0.0-rc.7'
 import $ from 'jsr:@david/dax@0.42.0'
 import * as R from 'npm:remeda@2.22.1'
+import {
+  formatFor,
+  type FormatOptions,
+  formats,
+  type Heading,
+  headingLine,
+} from './formats.ts'
+import { expandIncludes } from './includes.ts'
+import { loadNav, type Nav } from './nav.ts'
+import {
+  cost,
+  countTokens,
+  defaultPrices,
+  estimateTokens,
+  type JsonSchema,
+  type LlmRequest,
+  type Message,
+  modelName,
+  type Price,
+  type Provider,
+  providers,
+  type SystemBlock,
+  type Usage,
+  validateJson,
+} from './llm.ts'
 
 export interface Doc {
   relPath: string
+  /** Key into `formats` */
+  format: string
   content: string
   head: string
   headings: string
+  /** Parsed frontmatter, if the file had any */
+  frontmatter?: Record<string, unknown>
+  title?: string
+  description?: string
+  tags?: string[]
+  /** Sections of the docs site's navigation this page is under */
+  navPath?: string[]
 }
 
-function getIndex(dir: string): Promise<Doc[]> {
-  const files = walk(dir, { includeDirs: false, exts: ['md', 'adoc'] })
-  return Array.fromAsync(files, async ({ path }) => {
-    const content = await Deno.readTextFile(path)
-    const relPath = relative(dir, path)
-    const headingPattern = path.endsWith('.adoc') ? /^=+\s+.*/gm : /^#+\s+.*/gm
-    const headings = (content.match(headingPattern)?.map((h) => h.trim()) || []).join('\n')
-    const head = content.slice(0, 400)
-    return { relPath, content, head, headings }
+/** Headings of a doc, parsed according to its format */
+const findHeadings = (doc: Doc) => formats[doc.format].headings(doc.content)
+
+/** A file to index, as found by walking a directory or looking up man pages */
+interface CorpusFile {
+  relPath: string
+  path: string
+  format: string
+}
+
+async function readText(path: string) {
+  if (!path.endsWith('.gz')) return Deno.readTextFile(path)
+  // reading the stream to the end closes the file
+  const file = await Deno.open(path)
+  const text = file.readable
+    .pipeThrough(new DecompressionStream('gzip'))
+    .pipeThrough(new TextDecoderStream())
+  return (await Array.fromAsync(text)).join('')
+}
+
+/**
+ * Split YAML (`---`) or TOML (`+++`) frontmatter off the top of a file.
+ * Frontmatter that doesn't parse to an object is left alone as part of the body.
+ */
+export function splitFrontmatter(
+  raw: string,
+): { frontmatter?: Record<string, unknown>; body: string } {
+  const m = raw.match(/^(---|\+\+\+)[ \t]*\r?\n([\s\S]*?)\r?\n\1[ \t]*(\r?\n|$)/)
+  if (!m) return { body: raw }
+  try {
+    const data = m[1] === '---' ? parseYaml(m[2]) : parseToml(m[2])
+    if (!R.isPlainObject(data)) return { body: raw }
+    return { frontmatter: data, body: raw.slice(m[0].length) }
+  } catch {
+    return { body: raw }
+  }
+}
+
+const stringField = (value: unknown) =>
+  typeof value === 'string' && value.trim() ? value.trim() : undefined
+
+/** Pull the fields that help retrieval out of frontmatter, leaving the rest raw */
+function frontmatterFields(frontmatter: Record<string, unknown>) {
+  const { title, description, summary, tags, keywords } = frontmatter
+  const tagList = [tags, keywords].flatMap((t) =>
+    Array.isArray(t) ? t.map(String) : typeof t === 'string' ? t.split(/\s*,\s*/) : []
+  ).filter((t) => t)
+  return {
+    frontmatter,
+    title: stringField(title),
+    description: stringField(description) ?? stringField(summary),
+    tags: tagList.length > 0 ? R.unique(tagList) : undefined,
+  }
+}
+
+/**
+ * Build a Doc from a file, along with the absolute paths of any files it
+ * includes (or tried to), which the cache needs to check too. Only files under
+ * `root`, the corpus directory, can be included.
+ */
+async function readDoc(
+  { relPath, path, format }: CorpusFile,
+  opts: FormatOptions,
+  root: string,
+): Promise<{ doc: Doc; includes: string[] }> {
+  const raw = await readText(path).catch((e) => {
+    throw new Error(`Could not read ${relPath}: ${(e as Error).message}`)
   })
+  const { frontmatter, body } = formats[format].frontmatter
+    ? splitFrontmatter(raw)
+    : { frontmatter: undefined, body: raw }
+  const { text, includes } = await expandIncludes(path, format, body, root)
+  let content: string
+  try {
+    content = formats[format].toText?.(text, opts) ?? text
+  } catch (e) {
+    throw new Error(`Could not read ${relPath} as ${format}: ${(e as Error).message}`)
+  }
+  const headings = formats[format].headings(content).map(headingLine).join('\n')
+  const head = content.slice(0, 400)
+  const meta = frontmatter ? frontmatterFields(frontmatter) : {}
+  return { doc: { relPath, format, content, head, headings, ...meta }, includes }
 }
 
-const retrievalSystemPrompt = $.dedent`
-  You are a document retrieval system. Determine which of the provided documents are likely to be relevant to the user's question.
+/////////////////////////////
+// SECTIONS
+/////////////////////////////
 
-  - Return at most 4 documents, but return fewer if possible. Avoid returning irrelevant documents!
-  - Put most relevant documents first
-  - Your response MUST be a parseable JSON array of relative paths
+const slugify = (title: string) =>
+  title.toLowerCase().replace(/[^\p{L}\p{N}\s-]/gu, '').trim().replace(/\s+/g, '-')
+
+/**
+ * Split a doc into one Doc per heading, each running up to the next heading of
+ * any level. The relPath of each is a stable ID like `path#heading-slug`
+ * (with `-1`, `-2`, etc. on repeated slugs, like GitHub), and the content is
+ * prefixed with the ancestor headings so the section makes sense on its own.
+ * Anything before the first heading keeps the bare path as its ID.
+ */
+export function splitSections(doc: Doc): Doc[] {
+  const headings = findHeadings(doc)
+  if (headings.length === 0) return [doc]
+
+  const sections: Doc[] = []
+  const preamble = doc.content.slice(0, headings[0].offset)
+  if (preamble.trim()) {
+    sections.push({ ...doc, content: preamble, head: preamble.slice(0, 400), headings: '' })
+  }
+
+  const seen = new Map<string, number>()
+  const ancestors: Heading[] = []
+  headings.forEach((heading, i) => {
+    while (ancestors.length > 0 && ancestors.at(-1)!.level >= heading.level) ancestors.pop()
+    const slug = slugify(heading.title)
+    const count = seen.get(slug) ?? 0
+    seen.set(slug, count + 1)
+
+    const body = doc.content.slice(heading.offset, headings[i + 1]?.offset)
+    const context = ancestors.map(headingLine).join('\n')
+    sections.push({
+      ...doc,
+      relPath: `${doc.relPath}#${count > 0 ? `${slug}-${count}` : slug}`,
+      content: context ? `${context}\n\n${body}` : body,
+      head: body.slice(0, 400),
+      headings: [...ancestors, heading].map(headingLine).join('\n'),
+    })
+    ancestors.push(heading)
+  })
+  return sections
+}
+
+/////////////////////////////
+// CORPUS FILTERS
+/////////////////////////////
+
+interface IgnoreRule {
+  regex: RegExp
+  negate: boolean
+  dirOnly: boolean
+}
+
+/**
+ * Parse gitignore-style patterns. Patterns with a slash (other than a trailing
+ * one) are relative to the corpus root, the rest match at any depth.
+ */
+function parseIgnore(lines: string[]): IgnoreRule[] {
+  return lines
+    .map((line) => line.trimEnd())
+    .filter((line) => line && !line.startsWith('#'))
+    .map((line) => {
+      const negate = line.startsWith('!')
+      let pattern = negate ? line.slice(1) : line
+      if (pattern.startsWith('\\')) pattern = pattern.slice(1) // escaped leading # or !
+      const dirOnly = pattern.endsWith('/')
+      if (dirOnly) pattern = pattern.slice(0, -1)
+      const anchored = pattern.includes('/')
+      if (pattern.startsWith('/')) pattern = pattern.slice(1)
+      const glob = anchored ? pattern : `**/${pattern}`
+      const regex = globToRegExp(glob, { extended: true, globstar: true })
+      return { regex, negate, dirOnly }
+    })
+}
+
+/** Last matching rule wins, like git. Undefined means no rule matched. */
+function matchRules(rules: IgnoreRule[], relPath: string, isDir: boolean) {
+  let result: boolean | undefined
+  for (const rule of rules) {
+    if (rule.dirOnly && !isDir) continue
+    if (rule.regex.test(relPath)) result = !rule.negate
+  }
+  return result
+}
+
+async function readLines(path: string) {
+  try {
+    return (await Deno.readTextFile(path)).split('\n')
+  } catch (e) {
+    if (e instanceof Deno.errors.NotFound) return undefined
+    throw e
+  }
+}
+
+export interface Filter {
+  /** What's active, for showing the user */
+  summary: string[]
+  skipDir(relPath: string): boolean
+  skipFile(relPath: string): boolean
+}
+
+/**
+ * Combine the corpus's `.gitignore` and `.raggedyignore` with `--exclude`
+ * patterns (later ones take precedence) and `--include` globs. If there are
+ * any includes, a file has to match one of them to be indexed.
+ */
+export async function loadFilter(
+  dir: string,
+  include: string[],
+  exclude: string[],
+): Promise<Filter> {
+  const summary: string[] = []
+  const ignoreLines: string[] = []
+  for (const name of ['.gitignore', '.raggedyignore']) {
+    const lines = await readLines(join(dir, name))
+    if (!lines) continue
+    ignoreLines.push(...lines)
+    summary.push(`\`${name}\``)
+  }
+  const ignores = parseIgnore([...ignoreLines, ...exclude])
+  const includes = parseIgnore(include)
+  summary.push(...include.map((g) => `include \`${g}\``))
+  summary.push(...exclude.map((g) => `exclude \`${g}\``))
+
+  return {
+    summary,
+    skipDir: (relPath) => relPath === '.git' || !!matchRules(ignores, relPath, true),
+    skipFile: (relPath) =>
+      !!matchRules(ignores, relPath, false) ||
+      (includes.length > 0 && !matchRules(includes, relPath, false)),
+  }
+}
+
+/**
+ * Yield each indexable file under `dir`, in sorted order so the outline comes
+ * out the same every time (which helps prompt caching). Ignored directories
+ * are never descended into.
+ */
+async function* walkCorpus(
+  dir: string,
+  filter: Filter,
+  opts: FormatOptions,
+  sub = '',
+): AsyncGenerator<CorpusFile> {
+  const entries = await Array.fromAsync(Deno.readDir(join(dir, sub)))
+  entries.sort((a, b) => a.name.localeCompare(b.name))
+  for (const entry of entries) {
+    const relPath = sub ? `${sub}/${entry.name}` : entry.name
+    let isFile = entry.isFile
+    // follow symlinks to files, but not to directories, which could loop
+    if (entry.isSymlink) {
+      isFile = (await Deno.stat(join(dir, relPath)).catch(() => undefined))?.isFile ?? false
+    }
+    if (entry.isDirectory) {
+      if (!filter.skipDir(relPath)) yield* walkCorpus(dir, filter, opts, relPath)
+    } else if (isFile) {
+      const format = formatFor(relPath, opts)
+      if (format && !filter.skipFile(relPath)) {
+        yield { relPath, path: join(dir, relPath), format }
+      }
+    }
+  }
+}
+
+/////////////////////////////
+// MAN PAGES
+/////////////////////////////
+
+const MAN_PREFIX = 'man:'
+const isManCorpus = (corpus: string) => corpus.startsWith(MAN_PREFIX)
+
+/**
+ * Where to look for man pages. An empty entry in MANPATH means "the default
+ * path here", so we tack the defaults on in that case.
+ */
+function manDirs() {
+  const defaults = ['/usr/share/man', '/usr/local/share/man', '/opt/homebrew/share/man']
+  const manpath = Deno.env.get('MANPATH')
+  if (!manpath) return defaults
+  const dirs = manpath.split(':')
+  return dirs.includes('') ? [...dirs.filter((d) => d), ...defaults] : dirs
+}
+
+/**
+ * Man pages for a command and its subcommands, e.g., `man:git` finds
+ * `git.1.gz`, `git-commit.1.gz`, etc. in every `man<section>` directory. If a
+ * page shows up in more than one man dir, the first one wins, like `man`.
+ */
+async function* manPages(name: string, filter: Filter): AsyncGenerator<CorpusFile> {
+  const escaped = name.replace(/[.*+?^${}()|[\]\\]/g, '\\$&')
+  const pattern = new RegExp(`^${escaped}(-[^.]+)?\\.[0-9n]\\w*(\\.gz)?$`)
+  const seen = new Set<string>()
+  for (const manDir of manDirs()) {
+    const sections = await Array.fromAsync(Deno.readDir(manDir)).catch(() => [])
+    const sectionDirs = sections
+      .filter((e) => e.isDirectory && /^man\w+$/.test(e.name))
+      .map((e) => e.name)
+      .sort()
+    for (const section of sectionDirs) {
+      const pages = await Array.fromAsync(Deno.readDir(join(manDir, section)))
+      for (const page of pages.map((p) => p.name).filter((n) => pattern.test(n)).sort()) {
+        const relPath = `${section}/${page}`
+        if (seen.has(relPath) || filter.skipFile(relPath)) continue
+        seen.add(relPath)
+        yield { relPath, path: join(manDir, section, page), format: 'man' }
+      }
+    }
+  }
+}
+
+/////////////////////////////
+// HELP TEXT
+/////////////////////////////
+
+const HELP_PREFIX = 'help:'
+const isHelpCorpus = (corpus: string) => corpus.startsWith(HELP_PREFIX)
+
+/** `<command> --help`, or undefined if the command isn't there or says nothing */
+async function helpText(command: string[]): Promise<string | undefined> {
+  const result = await $`${command} --help`
+    .env('NO_COLOR', '1')
+    .stdin('null')
+    .stdout('piped')
+    .stderr('piped')
+    .timeout('10s')
+    .noThrow()
+  // some tools print their help to stderr
+  const text = result.stdout.trim() ? result.stdout : result.stderr
+  if (result.code !== 0 && !result.stdout.trim()) return undefined
+  return text.trim() ? text : undefined
+}
+
+/**
+ * Subcommands listed in `--help` output: the first word of each indented line
+ * under a heading that mentions commands (`Commands:`, `Available
+ * subcommands:`), up to the next heading. Aliases after a comma are skipped,
+ * but an indented line that's only a comma-separated list is taken whole.
+ */
+export function helpSubcommands(text: string): string[] {
+  const names: string[] = []
+  let listing = false
+  for (const line of text.split('\n')) {
+    if (!line.trim()) continue
+    // unindented lines without a colon are group captions, as in `git --help`
+    if (!/^\s/.test(line)) {
+      if (/:\s*$/.test(line)) listing = /commands\b.*:\s*$/i.test(line)
+      continue
+    }
+    if (!listing) continue
+    if (/^\s+[a-z][\w-]*(,\s*[a-z][\w-]*)+,?\s*$/.test(line)) {
+      names.push(...line.split(',').map((n) => n.trim()).filter((n) => n))
+      continue
+    }
+    // descriptions that wrap onto their own lines are indented further
+    const m = line.match(/^[ \t]{1,6}([a-z][\w-]*)(,\s*[\w-]+)*(\s{2,}|\t|\s+-\s|$)/)
+    if (m) names.push(m[1])
+  }
+  return R.unique(names).filter((name) => name !== 'help')
+}
+
+/**
+ * `--help` output for a command and the subcommands it lists, e.g.,
+ * `help:cargo` reads `cargo --help`, then `cargo build --help` and so on.
+ * Each becomes a doc named after the command line. They aren't cached, since
+ * they're quick to get and change whenever the tool is upgraded.
+ */
+async function helpDocs(command: string, filter: Filter): Promise<Doc[]> {
+  const words = command.split(/\s+/).filter((w) => w)
+  const top = await helpText(words)
+  if (top === undefined) throw new Error(`Could not get --help output from ${command}`)
+  const commands = [words, ...helpSubcommands(top).map((sub) => [...words, sub])]
+  const texts = [top, ...await mapLimit(commands.slice(1), 8, helpText)]
+  return commands.flatMap((cmd, i) => {
+    const raw = texts[i]
+    const relPath = `${cmd.join(' ')} --help`
+    if (raw === undefined || filter.skipFile(relPath)) return []
+    const content = formats.help.toText!(raw, { notebookOutputs: false, sourceCode: false })
+    const headings = formats.help.headings(content).map(headingLine).join('\n')
+    return [{ relPath, format: 'help', content, head: content.slice(0, 400), headings }]
+  })
+}
+
+/////////////////////////////
+// INDEX CACHE
+/////////////////////////////
+
+// Bump this when Doc or the way we build it changes so old caches get thrown out
+const CACHE_VERSION = 7
+
+interface CacheEntry {
+  mtime: number
+  size: number
+  doc: Doc
+  /** mtimes of included files by absolute path, null for ones that didn't exist */
+  includes?: Record<string, number | null>
+}
+
+interface IndexCache {
+  version: number
+  /** Docs built with different format options have to be rebuilt */
+  formatOptions: FormatOptions
+  entries: Record<string, CacheEntry>
+}
+
+/** XDG base directory from `envVar`, falling back to `~/<fallback>` */
+const xdgDir = (envVar: string, fallback: string) =>
+  Deno.env.get(envVar) || join(Deno.env.get('HOME') ?? '.', fallback)
+
+const cacheRoot = () => join(xdgDir('XDG_CACHE_HOME', '.cache'), 'raggedy')
+
+/**
+ * Each corpus gets its own directory, named by a hash of its absolute path
+ * (or its name, for man pages)
+ */
+async function cacheDir(corpus: string) {
+  const key = isManCorpus(corpus) ? corpus : resolve(corpus)
+  const bytes = new TextEncoder().encode(key)
+  const hash = new Uint8Array(await crypto.subtle.digest('SHA-256', bytes))
+  const hex = Array.from(hash.slice(0, 8), (b) => b.toString(16).padStart(2, '0')).join('')
+  return join(cacheRoot(), hex)
+}
+
+async function readCache(
+  file: string,
+  formatOptions: FormatOptions,
+): Promise<Record<string, CacheEntry>> {
+  try {
+    const cache: IndexCache = JSON.parse(await Deno.readTextFile(file))
+    const usable = cache.version === CACHE_VERSION &&
+      R.isDeepEqual(cache.formatOptions, formatOptions)
+    return usable ? cache.entries : {}
+  } catch (e) {
+    if (e instanceof Deno.errors.NotFound || e instanceof SyntaxError) return {}
+    throw e
+  }
+}
+
+async function writeCache(
+  file: string,
+  formatOptions: FormatOptions,
+  entries: Record<string, CacheEntry>,
+) {
+  const cache: IndexCache = { version: CACHE_VERSION, formatOptions, entries }
+  await Deno.mkdir(dirname(file), { recursive: true })
+  // write to a temp file and rename so an interrupted run can't leave a partial cache
+  const tmp = `${file}.${Deno.pid}.tmp`
+  await Deno.writeTextFile(tmp, JSON.stringify(cache))
+  await Deno.rename(tmp, file)
+}
+
+async function mtimeOf(path: string) {
+  try {
+    return (await Deno.stat(path)).mtime?.getTime() ?? 0
+  } catch (e) {
+    if (e instanceof Deno.errors.NotFound) return null
+    throw e
+  }
+}
+
+async function includesUnchanged(includes: CacheEntry['includes'] = {}) {
+  for (const [path, mtime] of Object.entries(includes)) {
+    if (await mtimeOf(path) !== mtime) return false
+  }
+  return true
+}
+
+export interface IndexOptions {
+  filter: Filter
+  formatOptions: FormatOptions
+  /** Read and write the on-disk cache */
+  cache: boolean
+  /** Leave out files that are included by other files in the corpus */
+  hidePartials: boolean
+}
+
+/**
+ * Walk the corpus and build a Doc for each file. The corpus is a directory,
+ * `man:<command>` for local man pages, or `help:<command>` for `--help`
+ * output, which skips the cache. With the cache on, files whose mtime
+ * and size match the cache (along with those of any files they include) are
+ * not re-read, and files that have been deleted since the last run fall out
+ * of the cache.
+ */
+export async function getIndex(
+  corpus: string,
+  { filter, formatOptions, cache: useCache, hidePartials }: IndexOptions,
+): Promise<Doc[]> {
+  if (isHelpCorpus(corpus)) return await helpDocs(corpus.slice(HELP_PREFIX.length), filter)
+  const file = join(await cacheDir(corpus), 'index.json')
+  const cached = useCache ? await readCache(file, formatOptions) : {}
+  let hits = 0
+
+  const files = isManCorpus(corpus)
+    ? manPages(corpus.slice(MAN_PREFIX.length), filter)
+    : walkCorpus(corpus, filter, formatOptions)
+  const read = await Array.fromAsync(files, async (corpusFile) => {
+    const { relPath, path } = corpusFile
+    const { mtime, size } = await Deno.stat(path)
+    const mtimeMs = mtime?.getTime() ?? 0
+    const hit = cached[relPath]
+    const fresh = hit && hit.mtime === mtimeMs && hit.size === size &&
+      await includesUnchanged(hit.includes)
+    if (fresh) {
+      hits++
+      return hit
+    }
+    // one bad file shouldn't stop the rest of the corpus from being indexed
+    let result
+    try {
+      result = await readDoc(corpusFile, formatOptions, corpus)
+    } catch (e) {
+      $.logWarn(`${(e as Error).message}, so skipping it`)
+      return undefined
+    }
+    const { doc, includes } = result
+    const entry: CacheEntry = { mtime: mtimeMs, size, doc }
+    if (includes.length > 0) {
+      const mtimes = await Promise.all(includes.map(mtimeOf))
+      entry.includes = R.fromEntries(R.zip(includes, mtimes))
+    }
+    return entry
+  })
+  const entries = read.filter((entry) => entry !== undefined)
+
+  // anything changed, added, or deleted means the cache needs rewriting
+  const stale = hits < entries.length || hits < Object.keys(cached).length
+  if (useCache && stale) {
+    const byPath = Object.fromEntries(entries.map((e) => [e.doc.relPath, e]))
+    await writeCache(file, formatOptions, byPath)
+  }
+  // empty docs stay in the cache so we don't keep re-reading them
+  const docs = entries.map((e) => e.doc).filter((doc) => doc.content.trim())
+  if (!hidePartials) return docs
+  const included = new Set(entries.flatMap((e) => Object.keys(e.includes ?? {})))
+  return docs.filter((doc) => !included.has(resolve(corpus, doc.relPath)))
+}
+
+/**
+ * Put docs in the order of the corpus's table of contents, with the titles and
+ * parent sections it gives them. Pages that aren't in the nav are left out.
+ * Returns the index as-is if there's no nav we can use.
+ */
+async function applyNav(
+  dir: string,
+  index: Doc[],
+  runSidebars: boolean,
+): Promise<{ docs: Doc[]; nav?: Nav }> {
+  let nav: Nav | undefined
+  try {
+    nav = await loadNav(dir, index.map((doc) => doc.relPath), runSidebars)
+  } catch (e) {
+    $.logWarn(`${(e as Error).message}, so ignoring it`)
+  }
+  if (!nav) return { docs: index }
+
+  const byPath = new Map(index.map((doc) => [doc.relPath, doc]))
+  const docs = nav.entries.flatMap(({ relPath, title, parents }) => {
+    const doc = byPath.get(relPath)
+    if (!doc) return []
+    const navPath = parents.length > 0 ? parents : undefined
+    return [{ ...doc, title: title || doc.title, navPath }]
+  })
+  if (docs.length === 0) {
+    $.logWarn(`None of the pages in ${nav.source} are in the index, so ignoring it`)
+    return { docs: index }
+  }
+  return { docs, nav }
+}
+
+/** Clear the cache for one corpus, or all of them */
+async function clearCache(corpus?: string) {
+  const target = corpus ? await cacheDir(corpus) : cacheRoot()
+  try {
+    await Deno.remove(target, { recursive: true })
+  } catch (e) {
+    if (!(e instanceof Deno.errors.NotFound)) throw e
+  }
+}
+
+/////////////////////////////
+// RETRIEVAL
+/////////////////////////////
+
+const responseFormat = $.dedent`
+  - Your response MUST be a parseable JSON object of the form {"paths": [...]} where each path is a relative path from the outline
     - Do NOT wrap the answer in a markdown code fence
     - Do NOT include any commentary or explanation
     - Do NOT attempt to answer the question
 `
 
-const outlineXml = (doc: Doc) =>
-  $.dedent`
-    <document>
-      <path>${doc.relPath}</path>
-      <sections>${doc.headings}</sections>
-      <head>${doc.head}</head>
-    </document>`
+// How many documents end up in the answer prompt is decided by the context budget
+const MAX_RETRIEVED = 10
+
+const retrievalSystemPrompt = $.dedent`
+  You are a document retrieval system. Determine which of the provided documents are likely to be relevant to the user's question.
+
+  - Return at most ${MAX_RETRIEVED} documents, but return fewer if possible. Avoid returning irrelevant documents!
+  - Put most relevant documents first
+` + '\n' + responseFormat
+
+const shortlistSystemPrompt = $.dedent`
+  You are a document retrieval system. The provided documents are one batch out of a larger corpus. Pick out the documents in this batch that could plausibly be relevant to the user's question. They will be ranked against candidates from the other batches in a later step.
+
+  - Return at most 8 documents. Return an empty list if nothing in this batch is relevant.
+  - Put most relevant documents first
+` + '\n' + responseFormat
+
+function outlineXml(doc: Doc) {
+  const fields = [
+    `<path>${doc.relPath}</path>`,
+    doc.title && `<title>${doc.title}</title>`,
+    doc.navPath && `<nav>${doc.navPath.join(' > ')}</nav>`,
+    doc.description && `<description>${doc.description}</description>`,
+    doc.tags && `<tags>${doc.tags.join(', ')}</tags>`,
+    `<sections>${doc.headings}</sections>`,
+    `<head>${doc.head}</head>`,
+  ]
+  return ['<document>', ...fields.filter((f) => f).map((f) => `  ${f}`), '</document>']
+    .join('\n')
+}
+
+/** Retrieval failed in a way the user should hear about without a stack trace */
+export class RetrievalError extends Error {}
+
+const pathsSchema: JsonSchema = {
+  type: 'object',
+  properties: {
+    paths: {
+      type: 'array',
+      description: 'Paths of relevant documents, most relevant first',
+      items: { type: 'string' },
+    },
+  },
+  required: ['paths'],
+}
 
 /**
- * Determine which subset of the documents is relevant to the question.
+ * Parse and validate a list of paths. Providers that don't enforce the schema
+ * sometimes wrap the JSON in a code fence or a line of prose, or give a bare
+ * array, so those are accepted too.
  */
-async function retrieve(index: Doc[], question: string, model: string) {
-  const systemPrompt = [
-    index.map(outlineXml).join('\n'),
-    retrievalSystemPrompt,
-  ].join('\n\n')
+export function parsePaths(text: string): { paths: string[] } | { error: string } {
+  const json = text.trim().replace(/^```\w*\n([\s\S]*)\n```$/, '$1')
+  let value: unknown
+  try {
+    value = JSON.parse(json)
+  } catch (e) {
+    // pull the object (or array) out of the surrounding text
+    const match = json.match(/\{[\s\S]*\}/) ?? json.match(/\[[\s\S]*\]/)
+    try {
+      value = JSON.parse(match?.[0] ?? '')
+    } catch {
+      return { error: `invalid JSON (${(e as Error).message})` }
+    }
+  }
+  if (Array.isArray(value) && value.every((p) => typeof p === 'string')) {
+    value = { paths: value }
+  }
+  const error = validateJson(value, pathsSchema)
+  return error ? { error } : (value as { paths: string[] })
+}
+
+/**
+ * Show the model an outline of `docs` and ask it which paths are relevant. If
+ * the answer doesn't fit the schema, the model gets one more try with the
+ * problem pointed out.
+ */
+async function askForPaths(
+  provider: Provider,
+  docs: Doc[],
+  question: string,
+  model: string,
+  instructions: string,
+) {
+  // The outline only changes when the corpus does, so it goes first and gets cached
+  const system: SystemBlock[] = [
+    { text: docs.map(outlineXml).join('\n'), cache: true },
+    { text: instructions },
+  ]
   const prompt =
-    `You are a document retrieval system. Determine which of the provided documents are likely to be relevant to the user's question. Do not answer the question, only give a JSON array of relevant documents.\n\n<question>${question}</question>`
-  const result = await $`ai -m ${model} -s ${systemPrompt} ${prompt} --raw`.text()
+    `You are a document retrieval system. Determine which of the provided documents are likely to be relevant to the user's question. Do not answer the question, only give a JSON object of the form {"paths": [...]}.\n\n<question>${question}</question>`
+  const messages: Message[] = [{ role: 'user', content: prompt }]
+  const usages: (Usage | undefined)[] = []
+  for (let attempt = 1; ; attempt++) {
+    const { text, usage } = await provider.complete({
+      model,
+      system,
+      messages,
+      schema: pathsSchema,
+    })
+    usages.push(usage)
+    const parsed = parsePaths(text)
+    if ('paths' in parsed) {
+      return { content: text, paths: parsed.paths, usage: sumUsage(usages) }
+    }
+    if (attempt === 2) {
+      const excerpt = text.length > 200 ? text.slice(0, 200) + '...' : text
+      throw new RetrievalError(
+        `The model didn't give a usable list of documents (${parsed.error}): ${excerpt}`,
+      )
+    }
+    messages.push(
+      { role: 'assistant', content: text },
+      {
+        role: 'user',
+        content:
+          `That response can't be used: ${parsed.error}. Reply with only a JSON object of the form {"paths": [...]}.`,
+      },
+    )
+  }
+}
 
-  // TODO: render narrowing result so we can see the price
-  // await renderMd(['# Narrowing response', result].join('\n\n'))
+/**
+ * Split the outline into batches that each fit in `budget` tokens, keeping
+ * documents from the same directory together where possible.
+ */
+function batchOutline(index: Doc[], budget: number): Doc[][] {
+  const batches: Doc[][] = []
+  let batch: Doc[] = []
+  let size = 0
+  const flush = () => {
+    if (batch.length > 0) batches.push(batch)
+    batch = []
+    size = 0
+  }
+  const byDir = R.groupBy(index, (doc) => dirname(doc.relPath))
+  for (const docs of Object.values(byDir)) {
+    const sizes = docs.map((doc) => estimateTokens(outlineXml(doc)))
+    // start a fresh batch rather than splitting a directory that would fit in one
+    if (size + R.sum(sizes) > budget) flush()
+    docs.forEach((doc, i) => {
+      if (size + sizes[i] > budget) flush()
+      batch.push(doc)
+      size += sizes[i]
+    })
+  }
+  flush()
+  return batches
+}
 
-  // Sometimes the model includes text other than the array, so pull out the array
-  const match = result.match(/\[[^\]]*\]/)?.[0]
-  if (!match) throw new Error('Could not find JSON array in response: ' + result)
+/** Like `Promise.all(items.map(fn))`, but with at most `limit` calls in flight */
+async function mapLimit<T, U>(
+  items: T[],
+  limit: number,
+  fn: (item: T) => Promise<U>,
+): Promise<U[]> {
+  const results: U[] = new Array(items.length)
+  let next = 0
+  const worker = async () => {
+    while (next < items.length) {
+      const i = next++
+      results[i] = await fn(items[i])
+    }
+  }
+  await Promise.all(Array.from({ length: Math.min(limit, items.length) }, worker))
+  return results
+}
 
-  try {
-    const paths: string[] = JSON.parse(match)
-    // 4 is the max system prompts cacheable in the Anthropic API
-    const docs = paths.slice(0, 4)
-      .map((p) => index.find((doc) => doc.relPath === p))
-      // TODO: warn if there's a path returned that's not in the array
-      .filter((x) => !!x)
-    return { content: result, docs, meta: '' }
-  } catch (e) {
-    console.error('Could not parse JSON', result)
-    throw e
+function sumUsage(usages: (Usage | undefined)[]): Usage | undefined {
+  const present = usages.filter((u) => !!u)
+  if (present.length === 0) return undefined
+  return {
+    input: R.sumBy(present, (u) => u.input),
+    output: R.sumBy(present, (u) => u.output),
+    cacheRead: R.sumBy(present, (u) => u.cacheRead ?? 0),
+    cacheWrite: R.sumBy(present, (u) => u.cacheWrite ?? 0),
   }
 }
 
+const retrievalModes = ['auto', 'single', 'hierarchical'] as const
+export type RetrievalMode = typeof retrievalModes[number]
+
+export interface RetrievalOptions {
+  /**
+   * `single` puts the whole outline in one prompt. `hierarchical` shortlists
+   * candidates from each batch of the outline and then ranks the shortlist.
+   * `auto` goes hierarchical only when the outline doesn't fit in one batch.
+   */
+  mode: RetrievalMode
+  /** Max outline tokens per batch in hierarchical mode */
+  batchTokens: number
+  /** Max batches in flight at once */
+  parallel: number
+}
+
+/** Edit distance between two strings */
+function levenshtein(a: string, b: string) {
+  let prev = Array.from({ length: b.length + 1 }, (_, j) => j)
+  for (let i = 1; i <= a.length; i++) {
+    const row = [i]
+    for (let j = 1; j <= b.length; j++) {
+      const cost = a[i - 1] === b[j - 1] ? 0 : 1
+      row[j] = Math.min(prev[j] + 1, row[j - 1] + 1, prev[j - 1] + cost)
+    }
+    prev = row
+  }
+  return prev[b.length]
+}
+
+// `guide.md#setup` -> `guide#setup`, so section IDs can be matched too
+const withoutExt = (path: string) => path.replace(/\.\w+(?=#|$)/, '')
+
+/**
+ * Find the doc the model meant when it returns a path that isn't in the
+ * outline. Tries, in order: different case, a missing directory prefix or
+ * corpus label, a different extension, and the closest path by edit distance
+ * if it's close enough. Anything ambiguous is not a match.
+ */
+export function repairPath(path: string, docs: Doc[]): Doc | undefined {
+  const lower = path.toLowerCase()
+  const tests: ((doc: Doc) => boolean)[] = [
+    (doc) => doc.relPath.toLowerCase() === lower,
+    (doc) => ['/', ':'].some((sep) => doc.relPath.toLowerCase().endsWith(sep + lower)),
+    (doc) => withoutExt(doc.relPath.toLowerCase()) === withoutExt(lower),
+  ]
+  for (const test of tests) {
+    const matches = docs.filter(test)
+    if (matches.length === 1) return matches[0]
+  }
+  const maxDistance = Math.max(2, Math.floor(path.length / 10))
+  const scored = docs.map((doc) => ({ doc, distance: levenshtein(path, doc.relPath) }))
+    .filter((s) => s.distance <= maxDistance)
+    .sort((a, b) => a.distance - b.distance)
+  const [best, next] = scored
+  if (best && (!next || best.distance < next.distance)) return best.doc
+}
+
+/** How many of the paths the model came up with weren't in the outline */
+export interface PathStats {
+  suggested: number
+  repaired: number
+  rejected: number
+}
+
+/**
+ * Look up the docs for paths returned by the model, repairing or dropping
+ * (with a warning) any that aren't in the outline
+ */
+function docsForPaths(paths: string[], docs: Doc[], stats: PathStats): Doc[] {
+  const byPath = new Map(docs.map((doc) => [doc.relPath, doc]))
+  const found = paths.map((path) => {
+    stats.suggested++
+    const doc = byPath.get(path)
+    if (doc) return doc
+    const repaired = repairPath(path, docs)
+    if (repaired) {
+      stats.repaired++
+      $.logWarn('Unknown path from retrieval:', `${path}, using ${repaired.relPath}`)
+    } else {
+      stats.rejected++
+      $.logWarn('Unknown path from retrieval:', `${path}, dropping it`)
+    }
+    return repaired
+  })
+  return R.unique(found.filter((doc) => !!doc))
+}
+
+/**
+ * Determine which subset of the documents is relevant to the question.
+ */
+export async function retrieve(
+  provider: Provider,
+  index: Doc[],
+  question: string,
+  model: string,
+  { mode, batchTokens, parallel }: RetrievalOptions,
+) {
+  let candidates = index
+  let shortlistUsage: Usage | undefined
+  const pathStats: PathStats = { suggested: 0, repaired: 0, rejected: 0 }
+  const outlineTokens = estimateTokens(index.map(outlineXml).join('\n'))
+  if (mode === 'hierarchical' || (mode === 'auto' && outlineTokens > batchTokens)) {
+    const batches = batchOutline(index, batchTokens)
+    const shortlists = await mapLimit(
+      batches,
+      parallel,
+      (batch) => askForPaths(provider, batch, question, model, shortlistSystemPrompt),
+    )
+    const shortlisted = new Set(
+      shortlists.flatMap((s, i) => docsForPaths(s.paths, batches[i], pathStats)),
+    )
+    // keep outline order so related docs stay together
+    candidates = index.filter((doc) => shortlisted.has(doc))
+    shortlistUsage = sumUsage(shortlists.map((s) => s.usage))
+    if (candidates.length === 0) {
+      const content = shortlists.map((s) => s.content).join('\n')
+      return { content, docs: [], usage: shortlistUsage, pathStats }
+    }
+  }
+
+  const ranked = await askForPaths(
+    provider,
+    candidates,
+    question,
+    model,
+    retrievalSystemPrompt,
+  )
+  const docs = docsForPaths(ranked.paths, candidates, pathStats).slice(0, MAX_RETRIEVED)
+  const usage = sumUsage([shortlistUsage, ranked.usage])
+  return { content: ranked.content, docs, usage, pathStats }
+}
+
 const systemMsgBase = `
 Answer the user's question concisely based on the above documentation.
 
@@ -85,79 +950,911 @@ Answer the user's question concisely based on the above documentation.
 * If you do not find the answer in the above sources, say so. You may speculate, but be clear that you are doing so.
 * Write naturally in prose. Do not overuse markdown headings and bullets.
 * Your answer must be in markdown format.
-* This is a one-time answer, not a chat, so don't prompt for followup questions
 `.trim()
 
-const answerSystemMsg = systemMsgBase + `
+const oneTimeMsg = `
+* This is a one-time answer, not a chat, so don't prompt for followup questions`
+
+const chatMsg = `
+* This is a chat. Earlier messages are context for the latest question, which is the one to answer.`
+
+const partialDocsMsg = `
 * The documentation may be truncated, so do not assume it is comprehensive of the corpus or even all relevant documents in the corpus.`
 
-const allDocsAnswerSystemMsg = systemMsgBase + `
+const allDocsMsg = `
 * You have access to the complete documentation corpus, so you can provide comprehensive answers.`
 
+const answerSystemMsg = systemMsgBase + oneTimeMsg + partialDocsMsg
+const allDocsAnswerSystemMsg = systemMsgBase + oneTimeMsg + allDocsMsg
+const chatSystemMsg = systemMsgBase + chatMsg + partialDocsMsg
+const allDocsChatSystemMsg = systemMsgBase + chatMsg + allDocsMsg
+
+function docXml(doc: Doc, truncated = false) {
+  const attrs = `path="${doc.relPath}"${truncated ? ' truncated="true"' : ''}`
+  return `<document ${attrs}>${doc.content}</document>`
+}
+
+/////////////////////////////
+// CONTEXT PACKING
+/////////////////////////////
+
+export interface PackedDoc {
+  doc: Doc
+  truncated: boolean
+}
+
+/**
+ * Cut the doc at the last heading that fits in `maxChars`, or failing that at
+ * the last paragraph break, line break, or just at `maxChars`, so the doc
+ * always contributes something if there's any room for it.
+ */
+function truncateAtHeading(doc: Doc, maxChars: number): string | undefined {
+  if (maxChars <= 0) return undefined
+  const cuts = findHeadings(doc)
+    .map((h) => h.offset)
+    .filter((offset) => offset > 0 && offset <= maxChars)
+  const breaks = ['\n\n', '\n'].map((sep) => doc.content.lastIndexOf(sep, maxChars))
+  const cut = cuts.at(-1) ?? breaks.find((i) => i > 0) ?? maxChars
+  return doc.content.slice(0, cut).trimEnd() || undefined
+}
+
+/**
+ * Take ranked docs in order until the token budget runs out. The doc that
+ * crosses the line is truncated, at a heading boundary if possible, and
+ * everything after it is dropped.
+ */
+export function packContext(docs: Doc[], budget: number): PackedDoc[] {
+  const packed: PackedDoc[] = []
+  let remaining = budget
+  for (const doc of docs) {
+    const tokens = estimateTokens(doc.content)
+    if (tokens <= remaining) {
+      packed.push({ doc, truncated: false })
+      remaining -= tokens
+      continue
+    }
+    const content = truncateAtHeading(doc, remaining * 4)
+    if (content) packed.push({ doc: { ...doc, content }, truncated: true })
+    break
+  }
+  return packed
+}
+
+/**
+ * Documents go in the system prompt ahead of the instructions, each in its own
+ * block, so the provider can cache them across questions.
+ */
+export function answerSystem(
+  packed: PackedDoc[],
+  instructions = answerSystemMsg,
+): SystemBlock[] {
+  const truncated = packed.filter((p) => p.truncated).map((p) => p.doc.relPath)
+  const note = truncated.length > 0
+    ? '\n* These documents were cut off to fit the context budget, so they may be missing sections relevant to the question: ' +
+      truncated.join(', ')
+    : ''
+  return [
+    ...packed.map((p) => ({ text: docXml(p.doc, p.truncated), cache: true })),
+    { text: instructions + note },
+  ]
+}
+
 /////////////////////////////
 // DISPLAY HELPERS
 /////////////////////////////
 
 const RENDERER = 'glow'
+const canRender = () => $.commandExistsSync(RENDERER) && Deno.stdout.isTerminal()
+
 async function renderMd(md: string, raw = false) {
-  if ($.commandExistsSync(RENDERER) && Deno.stdout.isTerminal() && !raw) {
+  if (canRender() && !raw) {
     await $`${RENDERER}`.stdinText(md)
   } else {
     console.log(md)
   }
 }
 
+/**
+ * glow can only render a complete document, so when we're using it we wait
+ * for the whole answer. Otherwise print chunks as they come in.
+ */
+export async function answer(provider: Provider, req: LlmRequest) {
+  if (canRender()) {
+    const completion = await $.progress('Answering...').with(() => provider.complete(req))
+    await renderMd(completion.text)
+    return completion
+  }
+  const encoder = new TextEncoder()
+  const completion = await provider.stream(
+    req,
+    (text) => Deno.stdout.writeSync(encoder.encode(text)),
+  )
+  console.log()
+  return completion
+}
+
+/** Tokens used by a step of the run, which may have taken several calls */
+interface StepUsage {
+  label: string
+  /** Full model name, for looking up the price */
+  model: string
+  usage: Usage | undefined
+}
+
+export interface UsageRow extends Required<Usage> {
+  label: string
+  /** Estimated dollars, if we know the model's price */
+  cost?: number
+}
+
+/** Usage and cost of each step that reported any, plus a total if there's more than one */
+function usageRows(steps: StepUsage[], prices: Record<string, Price>): UsageRow[] {
+  const rows: UsageRow[] = steps.flatMap(({ label, model, usage }) => {
+    if (!usage) return []
+    const price = prices[model]
+    const { input, output, cacheRead = 0, cacheWrite = 0 } = usage
+    const dollars = price && cost(usage, price)
+    return [{ label, input, output, cacheRead, cacheWrite, cost: dollars }]
+  })
+  if (rows.length < 2) return rows
+  const total = (key: keyof Usage) => R.sumBy(rows, (r) => r[key])
+  const knownCosts = rows.every((r) => r.cost !== undefined)
+  return [...rows, {
+    label: 'Total',
+    input: total('input'),
+    output: total('output'),
+    cacheRead: total('cacheRead'),
+    cacheWrite: total('cacheWrite'),
+    cost: knownCosts ? R.sumBy(rows, (r) => r.cost ?? 0) : undefined,
+  }]
+}
+
+/** Print usage as a small table, with `?` for costs of models we don't have prices for */
+function logUsageFooter(rows: UsageRow[]) {
+  if (rows.length === 0) return
+  const header = ['', 'in', 'out', 'cache read', 'cache write', 'cost']
+  const table = [header, ...rows.map((r) => [
+    r.label,
+    ...[r.input, r.output, r.cacheRead, r.cacheWrite].map((n) => numFmt.format(n)),
+    r.cost === undefined ? '?' : `$${r.cost.toFixed(4)}`,
+  ])]
+  const widths = header.map((_, i) => Math.max(...table.map((row) => row[i].length)))
+  for (const row of table) {
+    const cells = row.map((cell, i) =>
+      i === 0 ? cell.padEnd(widths[i]) : cell.padStart(widths[i])
+    )
+    $.logLight(cells.join('  '))
+  }
+}
+
+/////////////////////////////
+// CONFIG
+/////////////////////////////
+
+/** A named corpus, so `rgd <name> ...` works without spelling out the path */
+export interface CorpusConfig {
+  path: string
+  include?: string[]
+  exclude?: string[]
+  model?: string
+  threshold?: number
+}
+
+export interface Config {
+  /** Corpora up to this many tokens are sent whole instead of doing retrieval */
+  threshold?: number
+  corpora?: Record<string, CorpusConfig>
+  /** Prices by full model name, on top of the built-in ones */
+  prices?: Record<string, Price>
+}
+
+const configPath = () =>
+  join(xdgDir('XDG_CONFIG_HOME', '.config'), 'raggedy', 'config.toml')
+
+const isStrings = (value: unknown) =>
+  Array.isArray(value) && value.every((v) => typeof v === 'string')
+const isPositive = (value: unknown) => Number.isInteger(value) && (value as number) >= 1
+
+/** What's wrong with a corpus from the config file, if anything */
+function corpusProblem(corpus: Partial<Record<keyof CorpusConfig, unknown>>) {
+  if (typeof corpus.path !== 'string') return 'needs a path'
+  if (corpus.include !== undefined && !isStrings(corpus.include)) {
+    return 'has an include that is not a list of strings'
+  }
+  if (corpus.exclude !== undefined && !isStrings(corpus.exclude)) {
+    return 'has an exclude that is not a list of strings'
+  }
+  if (corpus.model !== undefined && typeof corpus.model !== 'string') {
+    return 'has a model that is not a string'
+  }
+  if (corpus.threshold !== undefined && !isPositive(corpus.threshold)) {
+    return 'has a threshold that is not a positive integer'
+  }
+}
+
+export async function loadConfig(): Promise<Config> {
+  const path = configPath()
+  let text: string
+  try {
+    text = await Deno.readTextFile(path)
+  } catch (e) {
+    if (e instanceof Deno.errors.NotFound) return {}
+    throw e
+  }
+  let config: Config
+  try {
+    config = parseToml(text) as Config
+  } catch (e) {
+    throw new Error(`Could not parse config file ${path}: ${(e as Error).message}`)
+  }
+  if (config.threshold !== undefined && !isPositive(config.threshold)) {
+    throw new Error(`The threshold in config file ${path} is not a positive integer`)
+  }
+  for (const [name, corpus] of Object.entries(config.corpora ?? {})) {
+    const problem = corpusProblem(corpus ?? {})
+    if (problem) throw new Error(`Corpus "${name}" in config file ${path} ${problem}`)
+  }
+  return config
+}
+
+const expandHome = (path: string) => path.replace(/^~(?=\/|$)/, Deno.env.get('HOME') ?? '~')
+
 /////////////////////////////
 // DO THE THING
 /////////////////////////////
 
-// if docs are shorter than this, don't bother narrowing
-const THRESHOLD = 500_000
+// if docs are shorter than this many tokens, don't bother narrowing
+const DEFAULT_THRESHOLD = 125_000
 
 const numFmt = Intl.NumberFormat()
 
-await new Command()
-  .name('rgd')
-  .description(`LLM-only RAG Q&A based on a directory of text files`)
-  .example('', "rgd ~/repos/helix/book/src 'turn off automatic bracket insertion'")
-  .helpOption('-h, --help', 'Show help')
-  .option('-m, --model <model>', 'Model to use', { default: 'flash' })
-  .arguments('<directory> <...query>')
-  .action(async ({ model }, dir, ...qParts) => {
-    const query = qParts.join(' ')
-    if (!query) throw new ValidationError('query is required')
-
-    const index = await getIndex(dir)
-
-    const totalDocsLength = R.sumBy(index, (doc) => doc.content.length)
-
-    if (totalDocsLength <= THRESHOLD) {
-      // Skip retrieval and use all docs
-      const len = numFmt.format(totalDocsLength)
-      await renderMd(
-        `Using full corpus (length in chars: ${len} < ${numFmt.format(THRESHOLD)})`,
-      )
-      const docsContent = index.map((doc) => `<document>${doc.content}</document>`).join(
-        '\n',
+/**
+ * A corpus to search, the label its paths get when there's more than one, and
+ * any settings for it from the config file
+ */
+interface Corpus extends Omit<CorpusConfig, 'include' | 'exclude'> {
+  label: string
+  include: string[]
+  exclude: string[]
+}
+
+/**
+ * `[label=]corpus`, where the corpus is a name from the config file or else a
+ * path. It's labeled with the name, or the last component of the path.
+ */
+function parseCorpus(arg: string, config: Config): Corpus {
+  const m = arg.match(/^([\w.-]+)=(.+)$/)
+  const label = m?.[1]
+  const spec = m ? m[2] : arg
+  const corpora = config.corpora ?? {}
+  if (Object.hasOwn(corpora, spec)) {
+    const { path, include = [], exclude = [], ...rest } = corpora[spec]
+    return { ...rest, label: label ?? spec, path: expandHome(path), include, exclude }
+  }
+  // the shell doesn't expand ~ after label=
+  const path = expandHome(spec)
+  const name = isManCorpus(path)
+    ? path.slice(MAN_PREFIX.length)
+    : isHelpCorpus(path)
+    ? path.slice(HELP_PREFIX.length).replace(/\s+/g, '-')
+    : basename(resolve(path))
+  return { label: label ?? name, path, include: [], exclude: [] }
+}
+
+interface CorpusOptions extends Omit<IndexOptions, 'filter'> {
+  include: string[]
+  exclude: string[]
+  nav: boolean
+  runSidebars: boolean
+}
+
+/**
+ * Index a corpus with its ignore files and nav applied. With `labeled`, paths
+ * get the corpus label in front so they stay unique across corpora.
+ */
+async function indexCorpus(
+  { label, path, ...corpus }: Corpus,
+  { include, exclude, nav: useNav, runSidebars, ...indexOpts }: CorpusOptions,
+  labeled: boolean,
+) {
+  const filter = await loadFilter(
+    path,
+    [...corpus.include, ...include],
+    [...corpus.exclude, ...exclude],
+  )
+  let docs = await getIndex(path, { filter, ...indexOpts })
+  if (useNav && !isManCorpus(path) && !isHelpCorpus(path)) {
+    const result = await applyNav(path, docs, runSidebars)
+    docs = result.docs
+    if (result.nav) filter.summary.push(`\`${result.nav.source}\``)
+  }
+  if (labeled) docs = docs.map((doc) => ({ ...doc, relPath: `${label}:${doc.relPath}` }))
+  return { label, docs, summary: filter.summary }
+}
+
+/** Say how many files came from where, if there's anything worth saying */
+function indexNote(corpora: Awaited<ReturnType<typeof indexCorpus>>[]) {
+  if (corpora.length === 1) {
+    const [{ docs, summary }] = corpora
+    if (summary.length === 0) return ''
+    return `\n\nIndexed ${docs.length} files, filtered by ${summary.join(', ')}`
+  }
+  const counts = corpora.map(({ label, docs, summary }) => {
+    const filtered = summary.length > 0 ? ` (filtered by ${summary.join(', ')})` : ''
+    return `${docs.length} files from \`${label}\`${filtered}`
+  })
+  return `\n\nIndexed ${counts.join(', ')}`
+}
+
+/** What `--json` prints: everything about the run in one object */
+export interface JsonOutput {
+  question: string
+  mode: 'full-corpus' | 'retrieval'
+  /** Why the full corpus was or wasn't sent */
+  reason: string
+  provider: string
+  model: string
+  /** Files in the index, after filtering */
+  indexed: number
+  retrieval?: {
+    /** The model's last retrieval response, as it came */
+    response: string
+    /** Retrieved docs in rank order, and how much of each went into context */
+    docs: { rank: number; path: string; context: 'full' | 'truncated' | 'skipped' }[]
+    pathStats: PathStats
+  }
+  /** Markdown, or null if there was nothing to answer from */
+  answer: string | null
+  /** Milliseconds spent on each step, and in total */
+  timings: Record<string, number>
+  usage: UsageRow[]
+}
+
+/** What `--json` prints instead when the run fails partway */
+export interface JsonError extends Pick<JsonOutput, 'question' | 'timings' | 'usage'> {
+  error: {
+    /**
+     * `setup` for config, index, and provider problems before any model call,
+     * `retrieval` when the model's list of documents was unusable, `provider`
+     * when deciding on the full corpus or retrieving failed, and `answer` when
+     * the answer step failed
+     */
+    kind: 'setup' | 'retrieval' | 'provider' | 'answer'
+    message: string
+  }
+}
+
+/** Options shared by one-off questions and chat */
+interface RunOptions {
+  provider: string
+  model?: string
+  baseUrl?: string
+  apiKey?: string
+  verbose?: boolean
+  cache: boolean
+  notebookOutputs?: boolean
+  source?: boolean
+  hidePartials?: boolean
+  nav: boolean
+  runSidebars?: boolean
+  include?: string[]
+  exclude?: string[]
+  retrieval: RetrievalMode
+  batchTokens: number
+  parallel: number
+  sections?: boolean
+  contextBudget: number
+  threshold?: number
+  fullCorpus?: boolean
+  forceRetrieval?: boolean
+}
+
+/** Load the config, index the corpora, and get the provider ready */
+async function setup(corpusArgs: string[], opts: RunOptions) {
+  const config = await loadConfig()
+  const corpora = corpusArgs.map((arg) => parseCorpus(arg, config))
+  const dupe = corpora.find((c, i) => corpora.findIndex((d) => d.label === c.label) < i)
+  if (dupe) {
+    throw new ValidationError(
+      `Two corpora are labeled "${dupe.label}", use label=path to rename one`,
+    )
+  }
+
+  const provider = providers[opts.provider]({
+    apiKey: opts.apiKey,
+    baseUrl: opts.baseUrl,
+  })
+  // with several corpora, the first one with a setting wins
+  const model = opts.model ?? corpora.find((c) => c.model)?.model ??
+    provider.defaultModel
+  const threshold = opts.threshold ?? corpora.find((c) => c.threshold)?.threshold ??
+    config.threshold ?? DEFAULT_THRESHOLD
+
+  const steps: StepUsage[] = []
+  const addUsage = (label: string, usage: Usage | undefined) =>
+    steps.push({ label, model: modelName(provider, model), usage })
+  // both cover the steps since the last time either was called
+  const takeUsage = () => usageRows(steps.splice(0), { ...defaultPrices, ...config.prices })
+  const showUsage = () => logUsageFooter(takeUsage())
+
+  const corpusOpts: CorpusOptions = {
+    include: opts.include ?? [],
+    exclude: opts.exclude ?? [],
+    nav: opts.nav,
+    runSidebars: !!opts.runSidebars,
+    formatOptions: {
+      notebookOutputs: !!opts.notebookOutputs,
+      sourceCode: !!opts.source,
+    },
+    cache: opts.cache,
+    hidePartials: !!opts.hidePartials,
+  }
+  const indexed = []
+  for (const corpus of corpora) {
+    indexed.push(await indexCorpus(corpus, corpusOpts, corpora.length > 1))
+  }
+  const index = indexed.flatMap((c) => c.docs)
+  const units = opts.sections ? index.flatMap(splitSections) : index
+  const filterNote = indexNote(indexed)
+  return {
+    provider,
+    model,
+    threshold,
+    index,
+    units,
+    filterNote,
+    addUsage,
+    takeUsage,
+    showUsage,
+  }
+}
+
+type Session = Awaited<ReturnType<typeof setup>>
+
+/**
+ * Whether to skip retrieval and send the whole corpus, and why. The request is
+ * only counted exactly when the estimate is close to the threshold.
+ */
+async function useFullCorpus(
+  { provider, model, threshold }: Session,
+  system: SystemBlock[],
+  question: string,
+  opts: RunOptions,
+) {
+  if (opts.fullCorpus) return { full: true, reason: '--full-corpus' }
+  if (opts.forceRetrieval) return { full: false, reason: '--force-retrieval' }
+  const estimate = estimateTokens(system.map((b) => b.text).join('\n'))
+  // Exact counting is an API call, so only do it when it could change the decision
+  const { tokens, exact } = estimate > threshold / 2 && estimate < threshold * 2
+    ? await countTokens(provider, {
+      model,
+      system,
+      messages: [{ role: 'user', content: question }],
+    })
+    : { tokens: estimate, exact: false }
+  const approx = exact ? '' : '~'
+  const full = tokens <= threshold
+  const reason = `${approx}${numFmt.format(tokens)} tokens ${full ? '<=' : '>'} ${
+    numFmt.format(threshold)
+  }`
+  return { full, reason }
+}
+
+/** One block for the whole corpus because there's a limit on cache breakpoints */
+const fullCorpusSystem = (index: Doc[], instructions: string): SystemBlock[] => [
+  { text: index.map((doc) => docXml(doc)).join('\n'), cache: true },
+  { text: instructions },
+]
+
+/** Run retrieval with a spinner, recording usage. Throws `RetrievalError`. */
+async function findDocs(session: Session, question: string, opts: RunOptions) {
+  const { provider, units, model } = session
+  const retrieved = await $.progress('Finding relevant files...')
+    .with(() =>
+      retrieve(provider, units, question, model, {
+        mode: opts.retrieval,
+        batchTokens: opts.batchTokens,
+        parallel: opts.parallel,
+      })
+    )
+  session.addUsage('Retrieval', retrieved.usage)
+  if (opts.verbose) {
+    const { suggested, repaired, rejected } = retrieved.pathStats
+    const counts = `${suggested} suggested, ${repaired} repaired, ${rejected} rejected`
+    $.logLight(`Retrieval paths: ${counts}`)
+  }
+  return retrieved
+}
+
+/** Markdown list of docs, noting the ones that didn't make it into context whole */
+function sourcesList(docs: Doc[], packed: PackedDoc[]) {
+  return docs.map((d, i) => {
+    const note = i >= packed.length
+      ? ' (over context budget, skipped)'
+      : packed[i].truncated
+      ? ' (truncated)'
+      : ''
+    return `- ${d.relPath}${note}`
+  }).join('\n')
+}
+
+/////////////////////////////
+// CHAT
+/////////////////////////////
+
+const NEED_MORE_DOCS = '<need-more-docs/>'
+
+const needMoreDocsMsg = `
+* If the documentation above doesn't cover the latest question, reply with only ${NEED_MORE_DOCS} and nothing else. More documents will be found and you'll be asked again.`
+
+const chatHelp = `
+- \`/sources\` lists the documents the conversation is based on
+- \`/add <path>\` adds a document to the conversation
+- \`/reset\` forgets the conversation and its documents
+- \`/quit\` leaves, as does Ctrl-D
+`.trim()
+
+/**
+ * A follow-up like "what about on Windows?" means nothing to retrieval on its
+ * own, so include the questions leading up to it
+ */
+function followUpQuery(history: Message[], question: string) {
+  const earlier = history.filter((m) => m.role === 'user').slice(-3)
+  if (earlier.length === 0) return question
+  const list = earlier.map((m) => `- ${m.content}`).join('\n')
+  return `${question}\n\nThis follows up on these earlier questions:\n${list}`
+}
+
+/**
+ * Answer questions until the user quits. Documents retrieved for one question
+ * stay in context for the rest of the conversation. Retrieval only runs again
+ * when the model says those documents don't cover a follow-up, and whatever it
+ * finds is added to them.
+ */
+async function chat(session: Session, opts: RunOptions) {
+  const { provider, model, index, units } = session
+  let full: boolean | undefined // decided on the first question after a reset
+  let history: Message[] = []
+  let context: Doc[] = []
+
+  const inContext = (doc: Doc) => context.some((d) => d.relPath === doc.relPath)
+
+  // returns how many docs were added
+  const addDocs = async (question: string) => {
+    const retrieved = await findDocs(session, question, opts).catch((e) => {
+      if (!(e instanceof RetrievalError)) throw e
+      $.logError('Retrieval failed:', e.message)
+      return undefined
+    })
+    const added = retrieved?.docs.filter((d) => !inContext(d)) ?? []
+    context = [...context, ...added]
+    if (added.length === 0) return 0
+    const packed = packContext(context, opts.contextBudget)
+    const offset = context.length - added.length
+    const list = sourcesList(context, packed).split('\n').slice(offset).join('\n')
+    await renderMd(['# Added to context', list].join('\n\n'))
+    return added.length
+  }
+
+  const reply = (messages: Message[], canAskForMore: boolean) => {
+    const system = full
+      ? fullCorpusSystem(index, allDocsChatSystemMsg)
+      : answerSystem(
+        packContext(context, opts.contextBudget),
+        chatSystemMsg + (canAskForMore ? needMoreDocsMsg : ''),
       )
-      const systemPrompt = `${allDocsAnswerSystemMsg}\n\n${docsContent}`
-      await $`ai -m ${model} -s ${systemPrompt} ${query}`
-      return
+    return $.progress('Answering...').with(() =>
+      provider.complete({ model, system, messages })
+    )
+  }
+
+  const ask = async (question: string) => {
+    if (full === undefined) {
+      const system = fullCorpusSystem(index, allDocsChatSystemMsg)
+      const decision = await useFullCorpus(session, system, question, opts)
+      full = decision.full
+      const mode = full ? `Using full corpus (${decision.reason})` : 'Using retrieval'
+      await renderMd(mode + session.filterNote)
+    }
+    let retrieved = false
+    if (!full && context.length === 0) {
+      retrieved = true
+      if (await addDocs(followUpQuery(history, question)) === 0) {
+        // same as a one-off question: nothing to answer from, so don't try
+        await renderMd('No relevant documents found')
+        return session.showUsage()
+      }
     }
+    const messages: Message[] = [...history, { role: 'user', content: question }]
+    let completion = await reply(messages, !retrieved)
+    session.addUsage('Answer', completion.usage)
+    if (completion.text.includes(NEED_MORE_DOCS)) {
+      const added = await addDocs(followUpQuery(history, question))
+      if (added === 0) $.logLight('No new relevant documents found')
+      completion = await reply(messages, false)
+      session.addUsage('Answer', completion.usage)
+    }
+    await renderMd(completion.text)
+    session.showUsage()
+    history = [...messages, { role: 'assistant', content: completion.text }]
+  }
 
-    // Use normal retrieval process
-    const retrieved = await $.progress('Finding relevant files...')
-      .with(() => retrieve(index, query, model))
-    const sources = retrieved.docs.length > 0
-      ? retrieved.docs.map((d) => `- ${d.relPath}`).join('\n')
-      : 'No relevant documents found'
-    await renderMd(['# Relevant files', sources].join('\n\n'))
+  const add = async (path: string) => {
+    if (full) return $.log('The whole corpus is already in context')
+    const doc = units.find((d) => d.relPath === path) ?? repairPath(path, units)
+    if (!doc) return $.logWarn('No such document:', path)
+    if (inContext(doc)) return $.log(`${doc.relPath} is already in context`)
+    context = [...context, doc]
+    $.log(`Added ${doc.relPath}`)
+  }
 
-    if (retrieved.docs.length === 0) return // no need for second call
+  const sources = async () => {
+    if (full) return await renderMd(`Using the full corpus, ${index.length} files`)
+    if (context.length === 0) return $.log('No documents yet')
+    const packed = packContext(context, opts.contextBudget)
+    await renderMd(['# Sources', sourcesList(context, packed)].join('\n\n'))
+  }
 
-    const docsContent = retrieved.docs.map((doc) => `<document>${doc.content}</document>`)
-      .join('\n')
-    const prompt = `${query}\n\n${docsContent}`
-    await $`ai -m ${model} -s ${answerSystemMsg} ${prompt}`
-  })
-  .parse(Deno.args)
+  $.logLight('Ask a question, or /help for commands')
+  while (true) {
+    const line = prompt('>')?.trim()
+    if (line === undefined) break
+    if (!line) continue
+    const [command, ...args] = line.split(/\s+/)
+    if (!command.startsWith('/')) {
+      const before = context
+      await ask(line).catch((e) => {
+        // leave the conversation as it was so the question can be asked again
+        context = before
+        $.logError('Failed to answer:', e instanceof Error ? e.message : String(e))
+      })
+    } else if (command === '/quit' || command === '/exit') break
+    else if (command === '/help') await renderMd(chatHelp)
+    else if (command === '/sources') await sources()
+    else if (command === '/add' && args.length > 0) for (const a of args) await add(a)
+    else if (command === '/reset') {
+      full = undefined
+      history = []
+      context = []
+      $.log('Started a new conversation')
+    } else $.logWarn(`Unknown command ${line}, try /help`)
+  }
+}
+
+/** Option type for counts and sizes, where 0 would leave nothing to work with */
+function positiveInteger({ label, name, value }: ArgumentValue): number {
+  const n = Number(value)
+  if (!Number.isInteger(n) || n < 1) {
+    throw new ValidationError(
+      `${label} "${name}" must be an integer of at least 1, got "${value}"`,
+    )
+  }
+  return n
+}
+
+if (import.meta.main) {
+  await new Command()
+    .name('rgd')
+    .description(`LLM-only RAG Q&A based on a directory of text files or man pages`)
+    .example('', "rgd ~/repos/helix/book/src 'turn off automatic bracket insertion'")
+    .example('Man pages', "rgd man:git 'undo the last commit but keep the changes'")
+    .example('Help text', "rgd help:cargo 'build without the default features'")
+    .example(
+      'Several corpora',
+      "rgd -d ours=~/work/docs -d ~/repos/jj/docs 'how do we configure signing'",
+    )
+    .example('Follow-up questions', 'rgd chat ~/repos/helix/book/src')
+    .helpOption('-h, --help', 'Show help')
+    .globalType('provider', new EnumType(Object.keys(providers)))
+    .globalOption(
+      '-d, --dir <corpus>',
+      'Corpus to search, as [label=]name or path (repeatable)',
+      { collect: true },
+    )
+    .globalOption(
+      '-p, --provider <provider:provider>',
+      'LLM backend',
+      { default: 'gemini' },
+    )
+    .globalOption('-v, --verbose', 'Show more detail about retrieval')
+    .globalOption('-m, --model <model>', 'Model to use (default depends on provider)')
+    .globalOption('--base-url <url>', 'Base URL for the provider API, e.g., a local server')
+    .globalOption('--api-key <key>', "API key, if not set in the provider's usual env var")
+    .globalOption('--no-cache', "Don't read or write the index cache")
+    .globalOption('--notebook-outputs', 'Include text outputs of Jupyter notebook cells')
+    .globalOption(
+      '--source',
+      'Also index doc comments in Rust, Python, and JS/TS source files',
+    )
+    .globalOption('--hide-partials', 'Leave out files that other files include (partials)')
+    .globalOption(
+      '--no-nav',
+      "Don't use SUMMARY.md, mkdocs.yml, or sidebars to order the index",
+    )
+    .globalOption('--run-sidebars', 'Run sidebars.js/.ts, sandboxed, to get the nav')
+    .globalOption('-i, --include <glob>', 'Only index files matching this glob', {
+      collect: true,
+    })
+    .globalOption(
+      '-x, --exclude <glob>',
+      'Skip files matching this gitignore-style pattern',
+      { collect: true },
+    )
+    .globalType('retrieval', new EnumType(retrievalModes))
+    .globalType('positive', positiveInteger)
+    .globalOption('--retrieval <mode:retrieval>', 'How to search the outline', {
+      default: 'auto' as const,
+    })
+    .globalOption(
+      '--batch-tokens <tokens:positive>',
+      'Max outline tokens per retrieval call',
+      { default: 100_000 },
+    )
+    .globalOption(
+      '--parallel <n:positive>',
+      'Max concurrent retrieval calls',
+      { default: 4 },
+    )
+    .globalOption(
+      '--sections',
+      'Retrieve heading-delimited sections instead of whole files',
+    )
+    .globalOption(
+      '--context-budget <tokens:positive>',
+      'Max tokens of documents to answer from',
+      { default: 100_000 },
+    )
+    .globalOption(
+      '--threshold <tokens:positive>',
+      `Send whole corpus if it's at most this many tokens (default ${DEFAULT_THRESHOLD})`,
+    )
+    .globalOption('--full-corpus', 'Skip retrieval and send the whole corpus', {
+      conflicts: ['force-retrieval'],
+    })
+    .globalOption(
+      '--force-retrieval',
+      'Do retrieval even if the corpus is under the threshold',
+    )
+    .option('--json', 'Print one JSON object with the answer, sources, timings, and usage')
+    .arguments('[corpus] [...query]')
+    .action(async (opts, first, ...rest) => {
+      // with -d, every argument is part of the query
+      const args = opts.dir ? [first, ...rest] : rest
+      const query = args.filter((a) => a !== undefined).join(' ')
+      const corpusArgs = opts.dir ?? (first ? [first] : [])
+      if (corpusArgs.length === 0) throw new ValidationError('corpus is required')
+      if (!query) throw new ValidationError('query is required')
+      const started = performance.now()
+      const timings: Record<string, number> = {}
+      const timed = async <T>(step: string, f: () => Promise<T>) => {
+        const start = performance.now()
+        try {
+          return await f()
+        } finally {
+          timings[step] = Math.round(performance.now() - start)
+        }
+      }
+
+      // until setup is done there's no usage to report
+      let takeUsage = (): UsageRow[] => []
+      const totals = () => ({
+        question: query,
+        timings: { ...timings, total: Math.round(performance.now() - started) },
+        usage: takeUsage(),
+      })
+      // what a failure is reported as under --json
+      let stage: JsonError['error']['kind'] = 'setup'
+
+      try {
+        const session = await timed('index', () => setup(corpusArgs, opts))
+        takeUsage = session.takeUsage
+        const { provider, model, index, filterNote, addUsage } = session
+        const show = (md: string) => opts.json ? Promise.resolve() : renderMd(md)
+        const respond = (req: LlmRequest) =>
+          timed('answer', () => opts.json ? provider.complete(req) : answer(provider, req))
+        const finish = (
+          result: Pick<JsonOutput, 'mode' | 'reason' | 'retrieval' | 'answer'>,
+        ) => {
+          if (!opts.json) return session.showUsage()
+          const output: JsonOutput = {
+            ...result,
+            provider: opts.provider,
+            model: modelName(provider, model),
+            indexed: index.length,
+            ...totals(),
+          }
+          console.log(JSON.stringify(output, null, 2))
+        }
+
+        const system = fullCorpusSystem(index, allDocsAnswerSystemMsg)
+        stage = 'provider'
+        const { full, reason } = await useFullCorpus(session, system, query, opts)
+        if (full) {
+          // Skip retrieval and use all docs
+          await show(`Using full corpus (${reason})${filterNote}`)
+          stage = 'answer'
+          const { text, usage } = await respond({
+            model,
+            system,
+            messages: [{ role: 'user', content: query }],
+          })
+          addUsage('Answer', usage)
+          return finish({ mode: 'full-corpus', reason, answer: text })
+        }
+
+        // Use normal retrieval process
+        const retrieved = await timed('retrieval', () => findDocs(session, query, opts))
+        const packed = packContext(retrieved.docs, opts.contextBudget)
+        const sources = retrieved.docs.length > 0
+          ? sourcesList(retrieved.docs, packed)
+          : 'No relevant documents found'
+        await show(['# Relevant files', sources].join('\n\n') + filterNote)
+        const retrieval = {
+          response: retrieved.content,
+          docs: retrieved.docs.map((d, i) => ({
+            rank: i + 1,
+            path: d.relPath,
+            context: i >= packed.length
+              ? 'skipped' as const
+              : packed[i].truncated
+              ? 'truncated' as const
+              : 'full' as const,
+          })),
+          pathStats: retrieved.pathStats,
+        }
+
+        if (packed.length === 0) { // no need for second call
+          return finish({ mode: 'retrieval', reason, retrieval, answer: null })
+        }
+
+        stage = 'answer'
+        const { text, usage } = await respond({
+          model,
+          system: answerSystem(packed),
+          messages: [{ role: 'user', content: query }],
+        })
+        addUsage('Answer', usage)
+        finish({ mode: 'retrieval', reason, retrieval, answer: text })
+      } catch (e) {
+        if (!(e instanceof RetrievalError) && !opts.json) throw e
+        const message = e instanceof Error ? e.message : String(e)
+        const kind = e instanceof RetrievalError ? 'retrieval' : stage
+        $.logError(kind === 'retrieval' ? 'Retrieval failed:' : 'Failed:', message)
+        if (opts.json) {
+          const output: JsonError = { ...totals(), error: { kind, message } }
+          console.log(JSON.stringify(output, null, 2))
+        }
+        Deno.exit(1)
+      }
+    })
+    .command(
+      'chat [...corpus]',
+      'Ask a question, then follow-up questions about the answer',
+    )
+    .action(async (opts, ...corpora) => {
+      const corpusArgs = [...(opts.dir ?? []), ...corpora]
+      if (corpusArgs.length === 0) throw new ValidationError('corpus is required')
+      await chat(await setup(corpusArgs, opts), opts)
+    })
+    .reset()
+    .command(
+      'cache',
+      new Command()
+        .description('Manage the index cache')
+        .action(function () {
+          this.showHelp()
+        })
+        .command(
+          'clear',
+          new Command()
+            .description('Delete cached indexes for one corpus, or all of them')
+            .arguments('[corpus]')
+            .action(async (_opts, corpus) => {
+              const path = corpus && parseCorpus(corpus, await loadConfig()).path
+              await clearCache(path)
+              $.log(corpus ? `Cleared cache for ${corpus}` : 'Cleared all cached indexes')
+            }),
+        ),
+    )
+    .parse(Deno.args)
+}
@@ -0,0 +1,334 @@
+import { assert, assertEquals, assertRejects } from 'jsr:@std/assert@1'
+import { join } from 'jsr:@std/path@1.0'
+import type { Completion, LlmRequest, Provider } from './llm.ts'
+import {
+  answerSystem,
+  type Doc,
+  getIndex,
+  helpSubcommands,
+  loadConfig,
+  loadFilter,
+  packContext,
+  parsePaths,
+  repairPath,
+  retrieve,
+  RetrievalError,
+  splitFrontmatter,
+  splitSections,
+} from './main.ts'
+
+const doc = (relPath: string, content = ''): Doc => ({
+  relPath,
+  format: 'markdown',
+  content,
+  head: content.slice(0, 400),
+  headings: '',
+})
+
+Deno.test('splitSections gives each heading its own doc with its ancestors', () => {
+  const content = [
+    'Intro.',
+    '# Setup',
+    'Install.',
+    '## Linux',
+    'apt.',
+    '## Linux',
+    'Again.',
+    '# Usage',
+    'Run.\n',
+  ].join('\n\n')
+  const sections = splitSections(doc('guide.md', content))
+  assertEquals(sections.map((s) => [s.relPath, s.content, s.headings]), [
+    ['guide.md', 'Intro.\n\n', ''],
+    ['guide.md#setup', '# Setup\n\nInstall.\n\n', '# Setup'],
+    ['guide.md#linux', '# Setup\n\n## Linux\n\napt.\n\n', '# Setup\n## Linux'],
+    ['guide.md#linux-1', '# Setup\n\n## Linux\n\nAgain.\n\n', '# Setup\n## Linux'],
+    ['guide.md#usage', '# Usage\n\nRun.\n', '# Usage'],
+  ])
+  assertEquals(sections[2].head, '## Linux\n\napt.\n\n')
+  const flat = doc('notes.md', 'No headings here.')
+  assertEquals(splitSections(flat), [flat])
+})
+
+Deno.test('splitFrontmatter reads YAML and TOML but leaves non-objects in the body', () => {
+  assertEquals(splitFrontmatter('---\ntitle: Install\ntags: [setup]\n---\n# Install\n'), {
+    frontmatter: { title: 'Install', tags: ['setup'] },
+    body: '# Install\n',
+  })
+  assertEquals(splitFrontmatter('+++\ntitle = "Install"\n+++\r\nBody'), {
+    frontmatter: { title: 'Install' },
+    body: 'Body',
+  })
+  for (const raw of ['---\njust a line\n---\nBody', '---\n- a\n- b\n---\nBody']) {
+    assertEquals(splitFrontmatter(raw), { body: raw })
+  }
+  assertEquals(splitFrontmatter('---\ntitle: [oops\n---\nBody').frontmatter, undefined)
+  assertEquals(splitFrontmatter('Text\n---\ntitle: x\n---'), {
+    body: 'Text\n---\ntitle: x\n---',
+  })
+})
+
+Deno.test('packContext truncates the doc that crosses the budget', () => {
+  const first = doc('first.md', 'a'.repeat(40)) // 10 tokens
+  const content = `# One\n\n${'x'.repeat(100)}\n\n# Two\n\n${'y'.repeat(200)}`
+  const second = doc('second.md', content)
+  const third = doc('third.md', 'z')
+  const packed = packContext([first, second, third], 50)
+  assertEquals(packed.map((p) => [p.doc.relPath, p.truncated]), [
+    ['first.md', false],
+    ['second.md', true],
+  ])
+  assertEquals(packed[1].doc.content, `# One\n\n${'x'.repeat(100)}`)
+})
+
+Deno.test('packContext cuts a doc with no breaks that fit rather than dropping it', () => {
+  const long = `line one\nline two\n${'z'.repeat(1000)}`
+  const [lines] = packContext([doc('lines.txt', long)], 5)
+  assertEquals(lines.doc.content, 'line one\nline two')
+  const [blob] = packContext([doc('blob.txt', 'z'.repeat(1000))], 10)
+  assertEquals([blob.doc.content, blob.truncated], ['z'.repeat(40), true])
+})
+
+Deno.test('repairPath finds the doc the model meant, unless it is ambiguous', () => {
+  const docs = [doc('guide/Install.md'), doc('jj:config.md')]
+  const repaired = (path: string) => repairPath(path, docs)?.relPath
+  assertEquals(repaired('guide/install.md'), 'guide/Install.md')
+  assertEquals(repaired('install.md'), 'guide/Install.md')
+  assertEquals(repaired('config.md'), 'jj:config.md')
+  assertEquals(repaired('guide/Install.rst'), 'guide/Install.md')
+  assertEquals(repaired('guide/Instal.md'), 'guide/Install.md')
+  assertEquals(repaired('somewhere/else.md'), undefined)
+  assertEquals(repairPath('config.md', [...docs, doc('ours:config.md')]), undefined)
+})
+
+Deno.test('helpSubcommands finds the commands a --help lists', () => {
+  const clap = `Rust's package manager
+
+Usage: cargo [OPTIONS] [COMMAND]
+
+Options:
+  -V, --version  Print version info and exit
+
+Commands:
+    build, b    Compile the current package
+    new         Create a new cargo package
+    help        Print this message
+    install
+            Install a Rust binary`
+  assertEquals(helpSubcommands(clap), ['build', 'new', 'install'])
+
+  const captioned = `These are common Git commands used in various situations:
+
+start a working area (see also: git help tutorial)
+   clone     Clone a repository into a new directory
+
+work on the current change (see also: git help everyday)
+   add       Add file contents to the index`
+  assertEquals(helpSubcommands(captioned), ['clone', 'add'])
+
+  const dashed = 'Most used commands:\n  list - list packages\n  show - show details'
+  assertEquals(helpSubcommands(dashed), ['list', 'show'])
+
+  const listed = `All commands:
+
+    access, adduser, help,
+    view
+
+Specify configs in the ini-formatted file:
+    /root/.npmrc`
+  assertEquals(helpSubcommands(listed), ['access', 'adduser', 'view'])
+})
+
+Deno.test('loadFilter applies gitignore rules, excludes, and includes', async () => {
+  const dir = await Deno.makeTempDir()
+  await Deno.writeTextFile(
+    join(dir, '.gitignore'),
+    'build/\n*.log\n!keep.log\n/docs/private.md\n',
+  )
+  try {
+    const filter = await loadFilter(dir, [], ['*.tmp'])
+    assertEquals(filter.summary, ['`.gitignore`', 'exclude `*.tmp`'])
+    assert(filter.skipDir('.git'))
+    assert(filter.skipDir('build'))
+    assert(filter.skipDir('src/build'))
+    assert(!filter.skipFile('build'))
+    assert(filter.skipFile('src/debug.log'))
+    assert(!filter.skipFile('src/keep.log'))
+    assert(filter.skipFile('docs/private.md'))
+    assert(!filter.skipFile('other/docs/private.md'))
+    assert(filter.skipFile('notes.tmp'))
+
+    const included = await loadFilter(dir, ['docs/*.md'], [])
+    assert(!included.skipFile('docs/a.md'))
+    assert(included.skipFile('docs/a.txt'))
+    assert(included.skipFile('other/a.md'))
+  } finally {
+    await Deno.remove(dir, { recursive: true })
+  }
+})
+
+Deno.test('loadConfig names the corpus with a bad setting', async () => {
+  const dir = await Deno.makeTempDir()
+  const previous = Deno.env.get('XDG_CONFIG_HOME')
+  Deno.env.set('XDG_CONFIG_HOME', dir)
+  await Deno.mkdir(join(dir, 'raggedy'))
+  const configFile = join(dir, 'raggedy', 'config.toml')
+  const write = (text: string) => Deno.writeTextFile(configFile, text)
+  try {
+    await write('[corpora.design]\npath = "~/design"\nexclude = ["drafts/**"]\n')
+    assertEquals((await loadConfig()).corpora?.design.exclude, ['drafts/**'])
+    await write('[corpora.design]\npath = "~/design"\nexclude = "drafts/**"\n')
+    await assertRejects(loadConfig, Error, 'Corpus "design" in config file')
+    await write('[corpora.design]\npath = "~/design"\nthreshold = -5\n')
+    await assertRejects(loadConfig, Error, 'threshold that is not a positive integer')
+    await write('threshold = "big"\n')
+    await assertRejects(loadConfig, Error, 'The threshold in config file')
+  } finally {
+    if (previous === undefined) Deno.env.delete('XDG_CONFIG_HOME')
+    else Deno.env.set('XDG_CONFIG_HOME', previous)
+    await Deno.remove(dir, { recursive: true })
+  }
+})
+
+Deno.test('the index cache re-reads only what changed', async () => {
+  const dir = await Deno.makeTempDir()
+  const cacheHome = await Deno.makeTempDir()
+  const previous = Deno.env.get('XDG_CACHE_HOME')
+  Deno.env.set('XDG_CACHE_HOME', cacheHome)
+  const write = (name: string, text: string) => Deno.writeTextFile(join(dir, name), text)
+  await write('a.md', '# A\n\nOld.\n')
+  await write('b.md', '# B\n\nBee.\n')
+  await write('main.md', '# Main\n\n{{#include part.md}}\n')
+  await write('part.md', 'Part one.\n')
+  const index = async () => {
+    const docs = await getIndex(dir, {
+      filter: await loadFilter(dir, [], []),
+      formatOptions: { notebookOutputs: false, sourceCode: false },
+      cache: true,
+      hidePartials: false,
+    })
+    return Object.fromEntries(docs.map((d) => [d.relPath, d.content]))
+  }
+  const cachedPaths = async () => {
+    const [hashDir] = await Array.fromAsync(Deno.readDir(join(cacheHome, 'raggedy')))
+    const file = join(cacheHome, 'raggedy', hashDir.name, 'index.json')
+    return Object.keys(JSON.parse(await Deno.readTextFile(file)).entries).sort()
+  }
+  try {
+    assertEquals((await index())['a.md'], '# A\n\nOld.\n')
+    assertEquals(await cachedPaths(), ['a.md', 'b.md', 'main.md', 'part.md'])
+
+    // same size and mtime means the cached doc is used without reading the file
+    const { mtime } = await Deno.stat(join(dir, 'a.md'))
+    await write('a.md', '# A\n\nNew.\n')
+    await Deno.utime(join(dir, 'a.md'), mtime!, mtime!)
+    assertEquals((await index())['a.md'], '# A\n\nOld.\n')
+    const later = new Date(mtime!.getTime() + 60_000)
+    await Deno.utime(join(dir, 'a.md'), later, later)
+    assertEquals((await index())['a.md'], '# A\n\nNew.\n')
+
+    await Deno.remove(join(dir, 'b.md'))
+    assertEquals(Object.keys(await index()).sort(), ['a.md', 'main.md', 'part.md'])
+    assertEquals(await cachedPaths(), ['a.md', 'main.md', 'part.md'])
+
+    // main.md itself is untouched, but the file it includes has changed
+    assert((await index())['main.md'].includes('Part one.'))
+    await write('part.md', 'Part two.\n')
+    await Deno.utime(join(dir, 'part.md'), later, later)
+    assert((await index())['main.md'].includes('Part two.'))
+  } finally {
+    if (previous === undefined) Deno.env.delete('XDG_CACHE_HOME')
+    else Deno.env.set('XDG_CACHE_HOME', previous)
+    await Deno.remove(dir, { recursive: true })
+    await Deno.remove(cacheHome, { recursive: true })
+  }
+})
+
+/** Answers retrieval calls with `paths` in turn, and anything else with `text` */
+function fakeProvider(paths: string[], text: string) {
+  const requests: LlmRequest[] = []
+  const reply = (req: LlmRequest): Completion => {
+    requests.push(req)
+    const usage = { input: 100, output: 10 }
+    return { text: req.schema ? paths.shift()! : text, usage }
+  }
+  const provider: Provider = {
+    name: 'fake',
+    defaultModel: 'fake-model',
+    complete: (req) => Promise.resolve(reply(req)),
+    stream: (req, onText) => {
+      const completion = reply(req)
+      onText(completion.text)
+      return Promise.resolve(completion)
+    },
+  }
+  return { provider, requests }
+}
+
+const retrievalOpts = { mode: 'auto' as const, batchTokens: 100_000, parallel: 2 }
+
+Deno.test('a question goes from corpus to answer', async () => {
+  const dir = await Deno.makeTempDir()
+  await Deno.mkdir(join(dir, 'drafts'))
+  await Deno.writeTextFile(join(dir, '.gitignore'), 'drafts/\n')
+  await Deno.writeTextFile(join(dir, 'alpha.md'), '# Alpha\n\nAlpha is the first letter.\n')
+  await Deno.writeTextFile(join(dir, 'beta.md'), '# Beta\n\nThe answer is 42.\n')
+  await Deno.writeTextFile(join(dir, 'drafts', 'gamma.md'), '# Gamma\n\nNot done yet.\n')
+  try {
+    const index = await getIndex(dir, {
+      filter: await loadFilter(dir, [], []),
+      formatOptions: { notebookOutputs: false, sourceCode: false },
+      cache: false,
+      hidePartials: false,
+    })
+    assertEquals(index.map((d) => d.relPath).sort(), ['alpha.md', 'beta.md'])
+
+    // the model gets the case wrong, and the path is repaired
+    const { provider, requests } = fakeProvider(['{"paths": ["Beta.md"]}'], 'It is 42.')
+    const question = 'What is the answer?'
+    const retrieved = await retrieve(provider, index, question, 'fake-model', retrievalOpts)
+    assertEquals(retrieved.docs.map((d) => d.relPath), ['beta.md'])
+    assertEquals(retrieved.pathStats, { suggested: 1, repaired: 1, rejected: 0 })
+    assertEquals(retrieved.usage, { input: 100, output: 10, cacheRead: 0, cacheWrite: 0 })
+    const outline = JSON.stringify(requests[0].system)
+    assert(outline.includes('alpha.md') && !outline.includes('gamma'))
+
+    const packed = packContext(retrieved.docs, 100_000)
+    const completion = await provider.complete({
+      model: 'fake-model',
+      system: answerSystem(packed),
+      messages: [{ role: 'user', content: question }],
+    })
+    assertEquals(completion.text, 'It is 42.')
+    const system = JSON.stringify(requests[1].system)
+    assert(system.includes('The answer is 42.') && !system.includes('Alpha is'))
+    assertEquals(requests[1].messages, [{ role: 'user', content: question }])
+  } finally {
+    await Deno.remove(dir, { recursive: true })
+  }
+})
+
+Deno.test('parsePaths accepts paths wrapped in a fence, prose, or a bare array', () => {
+  const expected = { paths: ['a.md', 'b.md'] }
+  assertEquals(parsePaths('{"paths": ["a.md", "b.md"]}'), expected)
+  assertEquals(parsePaths('```json\n{"paths": ["a.md", "b.md"]}\n```'), expected)
+  assertEquals(parsePaths('Relevant documents:\n{"paths": ["a.md", "b.md"]}'), expected)
+  assertEquals(parsePaths('["a.md", "b.md"]'), expected)
+  assertEquals(parsePaths('These look relevant: ["a.md", "b.md"]'), expected)
+  assert('error' in parsePaths('[1, 2]'))
+  assert('error' in parsePaths('a.md, I think'))
+})
+
+Deno.test('retrieval retries once after an unusable response', async () => {
+  const docs = [doc('a.md', 'A'), doc('b.md', 'B')]
+  const { provider, requests } = fakeProvider(['a.md, I think', '{"paths": ["a.md"]}'], '')
+  const retrieved = await retrieve(provider, docs, 'a?', 'fake-model', retrievalOpts)
+  assertEquals(retrieved.docs.map((d) => d.relPath), ['a.md'])
+  assertEquals(requests[1].messages.length, 3)
+
+  const stubborn = fakeProvider(['nope', 'still nope'], '')
+  await assertRejects(
+    () => retrieve(stubborn.provider, docs, 'a?', 'fake-model', retrievalOpts),
+    RetrievalError,
+  )
+})
@@ -0,0 +1,272 @@
+import { extname, join, resolve, toFileUrl } from 'jsr:@std/path@1.0'
+import { parse as parseYaml } from 'jsr:@std/yaml@1'
+import $ from 'jsr:@david/dax@0.42.0'
+
+/** A page in a docs site's table of contents */
+export interface NavEntry {
+  relPath: string
+  title?: string
+  /** Titles of the sections the page is nested under, outermost first */
+  parents: string[]
+}
+
+/** Table of contents of a corpus, in reading order */
+export interface Nav {
+  /** The file it came from, relative to the corpus root */
+  source: string
+  entries: NavEntry[]
+}
+
+async function readOptional(path: string) {
+  try {
+    return await Deno.readTextFile(path)
+  } catch (e) {
+    if (e instanceof Deno.errors.NotFound) return undefined
+    throw e
+  }
+}
+
+/** Links in nav files are URL-ish: drop the fragment and decode escapes */
+const linkPath = (prefix: string, link: string) =>
+  join(prefix, decodeURI(link.replace(/#.*/, '')))
+
+const isExternal = (link: string) => /^\w+:/.test(link)
+
+/////////////////////////////
+// MDBOOK
+/////////////////////////////
+
+/**
+ * mdBook's `SUMMARY.md`: a list of links, nested by indentation, optionally
+ * split into parts by headings. The first heading is the book's title and
+ * doesn't count as a part, and a `---` separator ends the last part. Draft
+ * chapters (empty links) have no file but still count as parents of whatever
+ * is nested under them.
+ */
+export function summaryNav(text: string, prefix: string): NavEntry[] {
+  const entries: NavEntry[] = []
+  let part: string | undefined
+  let seenContent = false
+  const stack: { indent: number; title: string }[] = []
+  for (const line of text.split('\n')) {
+    const heading = line.match(/^#+\s+(.*)/)
+    if (heading) {
+      if (seenContent) part = heading[1].trim()
+      seenContent = true
+      stack.length = 0
+      continue
+    }
+    // a separator comes before the suffix chapters, which aren't in any part
+    if (/^-{3,}\s*$/.test(line)) {
+      part = undefined
+      stack.length = 0
+      continue
+    }
+    const link = line.match(/^(\s*)(?:[-*]\s+)?\[([^\]]*)\]\(([^)]*)\)/)
+    if (!link) continue
+    seenContent = true
+    const [, space, title, target] = link
+    const indent = space.replace(/\t/g, '    ').length
+    while (stack.length > 0 && stack.at(-1)!.indent >= indent) stack.pop()
+    const parents = [...(part ? [part] : []), ...stack.map((s) => s.title)]
+    if (target && !isExternal(target)) {
+      entries.push({ relPath: linkPath(prefix, target), title, parents })
+    }
+    stack.push({ indent, title })
+  }
+  return entries
+}
+
+async function mdbookNav(dir: string): Promise<Nav | undefined> {
+  for (const prefix of ['', 'src']) {
+    const source = join(prefix, 'SUMMARY.md')
+    const text = await readOptional(join(dir, source))
+    if (text !== undefined) return { source, entries: summaryNav(text, prefix) }
+  }
+}
+
+/////////////////////////////
+// MKDOCS
+/////////////////////////////
+
+export type MkdocsItem = string | Record<string, string | MkdocsItem[]>
+
+export function mkdocsEntries(
+  items: MkdocsItem[],
+  prefix: string,
+  parents: string[],
+): NavEntry[] {
+  return items.flatMap((item): NavEntry[] => {
+    if (typeof item === 'string') {
+      return isExternal(item) ? [] : [{ relPath: linkPath(prefix, item), parents }]
+    }
+    return Object.entries(item).flatMap(([title, value]) => {
+      if (Array.isArray(value)) return mkdocsEntries(value, prefix, [...parents, title])
+      if (typeof value !== 'string' || isExternal(value)) return []
+      return [{ relPath: linkPath(prefix, value), title, parents }]
+    })
+  })
+}
+
+async function mkdocsNav(dir: string): Promise<Nav | undefined> {
+  const source = 'mkdocs.yml'
+  const text = await readOptional(join(dir, source))
+  if (text === undefined) return undefined
+  // Tags like !ENV, !relative, and !!python/name would trip up the YAML parser.
+  // Dropping them leaves their plain value, and we only care about the nav anyway.
+  const untagged = text.replace(/(?<=^|[\s[{,])!!?[\w./:-]+(?=[\s,\]}]|$)/gm, '')
+  const config = parseYaml(untagged) as {
+    docs_dir?: string
+    nav?: MkdocsItem[]
+  }
+  // Without a nav, MkDocs lists every page alphabetically, which tells us nothing
+  if (!Array.isArray(config?.nav)) return undefined
+  return { source, entries: mkdocsEntries(config.nav, config.docs_dir ?? 'docs', []) }
+}
+
+/////////////////////////////
+// DOCUSAURUS
+/////////////////////////////
+
+export interface SidebarObject {
+  type?: string
+  id?: string
+  label?: string
+  items?: SidebarItem[]
+  link?: { type?: string; id?: string }
+  dirName?: string
+}
+
+/** Doc ID, full item, or `{ 'Category label': [items] }` shorthand */
+export type SidebarItem = string | SidebarObject | Record<string, SidebarItem[]>
+
+/**
+ * Docusaurus refers to docs by ID: the path under `docs/` without the
+ * extension, and with number prefixes used for ordering (`01-intro.md`)
+ * stripped. Map both forms to paths in the corpus.
+ */
+export function docIds(relPaths: string[]) {
+  const ids = new Map<string, string>()
+  for (const relPath of relPaths) {
+    if (!relPath.startsWith('docs/')) continue
+    const id = relPath.slice('docs/'.length, -extname(relPath).length)
+    ids.set(id, relPath)
+    ids.set(id.replace(/(^|\/)\d+[-_.]/g, '$1'), relPath)
+  }
+  return ids
+}
+
+export function sidebarEntries(
+  items: SidebarItem[],
+  ids: Map<string, string>,
+  parents: string[],
+): NavEntry[] {
+  const doc = (id: string | undefined, title?: string): NavEntry[] => {
+    const relPath = id && ids.get(id)
+    return relPath ? [{ relPath, title, parents }] : []
+  }
+  return items.flatMap((item): NavEntry[] => {
+    if (typeof item === 'string') return doc(item)
+    if (!('type' in item) && !('id' in item)) {
+      return Object.entries(item as Record<string, SidebarItem[]>)
+        .flatMap(([label, sub]) => sidebarEntries(sub, ids, [...parents, label]))
+    }
+    const { type, id, label, items: sub, link, dirName } = item as SidebarObject
+    if (type === 'category') {
+      const index = link?.type === 'doc' ? doc(link.id, label) : []
+      return [...index, ...sidebarEntries(sub ?? [], ids, [...parents, label ?? ''])]
+    }
+    if (type === 'autogenerated') {
+      const dir = join('docs', dirName ?? '.') + '/'
+      const inDir = [...new Set(ids.values())].filter((p) => p.startsWith(dir)).sort()
+      return inDir.map((relPath) => ({ relPath, parents }))
+    }
+    if (type === 'doc' || type === 'ref' || type === undefined) return doc(id, label)
+    return [] // links and raw HTML
+  })
+}
+
+// Run by a separate Deno to print the sidebars in a file as JSON
+const sidebarsLoader = `
+const [path, url] = Deno.args
+let sidebars
+try {
+  const mod = await import(url)
+  sidebars = mod.default ?? mod
+} catch (e) {
+  if (path.endsWith('.ts')) throw e
+  const module = { exports: {} }
+  new Function('module', 'exports', await Deno.readTextFile(path))(module, module.exports)
+  sidebars = module.exports
+}
+console.log(JSON.stringify(sidebars))
+`
+
+/**
+ * Load a sidebars file, which is usually code. ES modules (including
+ * TypeScript) are imported and CommonJS gets run with a stand-in for `module`,
+ * just like Docusaurus does, but in a Deno that can only read the corpus, so
+ * the code can't get at the environment or the network.
+ */
+async function loadSidebars(
+  dir: string,
+  path: string,
+): Promise<Record<string, SidebarItem[]>> {
+  if (path.endsWith('.json')) return JSON.parse(await Deno.readTextFile(path))
+  const url = toFileUrl(resolve(path)).href
+  const sandbox = `--allow-read=${resolve(dir)}`
+  const json = await $`deno run --quiet --no-prompt --no-config ${sandbox} - ${path} ${url}`
+    .stdinText(sidebarsLoader).text()
+  return JSON.parse(json)
+}
+
+const sidebarFiles = ['sidebars.json', 'sidebars.js', 'sidebars.cjs', 'sidebars.ts']
+
+async function docusaurusNav(
+  dir: string,
+  relPaths: string[],
+  runSidebars: boolean,
+): Promise<Nav | undefined> {
+  for (const source of sidebarFiles) {
+    const path = join(dir, source)
+    if (await readOptional(path) === undefined) continue
+    if (!runSidebars && !source.endsWith('.json')) {
+      $.logLight(`Not running ${source} without --run-sidebars, so ignoring it`)
+      return undefined
+    }
+    const sidebars = await loadSidebars(dir, path)
+    const ids = docIds(relPaths)
+    const entries = Object.values(sidebars).flatMap((items) =>
+      Array.isArray(items) ? sidebarEntries(items, ids, []) : []
+    )
+    return { source, entries }
+  }
+}
+
+/////////////////////////////
+// LOADING
+/////////////////////////////
+
+/**
+ * Find the table of contents of a docs site rooted at `dir`, if there is one.
+ * `relPaths` are the files in the corpus, which some generators need to turn
+ * their own IDs into paths. A page listed more than once keeps its first spot.
+ * Sidebars written as code are only run with `runSidebars`.
+ */
+export async function loadNav(
+  dir: string,
+  relPaths: string[],
+  runSidebars = false,
+): Promise<Nav | undefined> {
+  let nav: Nav | undefined
+  try {
+    nav = await mdbookNav(dir) ?? await mkdocsNav(dir) ??
+      await docusaurusNav(dir, relPaths, runSidebars)
+  } catch (e) {
+    throw new Error(`Could not read the docs navigation in ${dir}: ${(e as Error).message}`)
+  }
+  if (!nav) return undefined
+  const seen = new Set<string>()
+  const entries = nav.entries.filter((e) => !seen.has(e.relPath) && seen.add(e.relPath))
+  return { ...nav, entries }
+}
@@ -0,0 +1,95 @@
+import { assertEquals } from 'jsr:@std/assert@1'
+import { docIds, mkdocsEntries, sidebarEntries, summaryNav } from './nav.ts'
+
+Deno.test('summaryNav nests by indentation and parts', () => {
+  const summary = `# Summary
+
+[Introduction](README.md)
+
+# User Guide
+
+- [Installation](guide/installation.md)
+  - [Sub thing](guide/sub%20thing.md#x)
+  - [Draft]()
+    - [Under draft](guide/draft.md)
+- [Reading](guide/reading.md)
+- [Elsewhere](https://example.com)
+
+---
+
+[Contributors](misc/contributors.md)
+`
+  assertEquals(summaryNav(summary, 'src'), [
+    { relPath: 'src/README.md', title: 'Introduction', parents: [] },
+    {
+      relPath: 'src/guide/installation.md',
+      title: 'Installation',
+      parents: ['User Guide'],
+    },
+    {
+      relPath: 'src/guide/sub thing.md',
+      title: 'Sub thing',
+      parents: ['User Guide', 'Installation'],
+    },
+    {
+      relPath: 'src/guide/draft.md',
+      title: 'Under draft',
+      parents: ['User Guide', 'Installation', 'Draft'],
+    },
+    { relPath: 'src/guide/reading.md', title: 'Reading', parents: ['User Guide'] },
+    { relPath: 'src/misc/contributors.md', title: 'Contributors', parents: [] },
+  ])
+})
+
+Deno.test('mkdocsEntries follows sections and skips external links', () => {
+  const nav = [
+    { Home: 'index.md' },
+    { 'User Guide': ['guide/a.md', { Writing: 'guide/w.md' }, { Site: 'https://x.org' }] },
+  ]
+  assertEquals(mkdocsEntries(nav, 'docs', []), [
+    { relPath: 'docs/index.md', title: 'Home', parents: [] },
+    { relPath: 'docs/guide/a.md', parents: ['User Guide'] },
+    { relPath: 'docs/guide/w.md', title: 'Writing', parents: ['User Guide'] },
+  ])
+})
+
+Deno.test('docIds maps ids with and without number prefixes', () => {
+  const ids = docIds([
+    'docs/intro.md',
+    'docs/01-guides/02-setup.md',
+    'docs/03-api.mdx',
+    'README.md',
+  ])
+  assertEquals(ids.get('intro'), 'docs/intro.md')
+  assertEquals(ids.get('guides/setup'), 'docs/01-guides/02-setup.md')
+  assertEquals(ids.get('01-guides/02-setup'), 'docs/01-guides/02-setup.md')
+  assertEquals(ids.get('api'), 'docs/03-api.mdx')
+  assertEquals(ids.size, 5)
+})
+
+Deno.test('sidebarEntries handles categories, autogenerated dirs, and shorthand', () => {
+  const ids = docIds([
+    'docs/intro.md',
+    'docs/guides/setup.md',
+    'docs/api/a.md',
+    'docs/api/b.md',
+  ])
+  const sidebar = [
+    'intro',
+    {
+      type: 'category',
+      label: 'Guides',
+      link: { type: 'doc', id: 'guides/setup' },
+      items: [{ type: 'autogenerated', dirName: 'api' }],
+    },
+    { type: 'link', label: 'Blog', href: 'https://example.com' },
+    { Reference: [{ type: 'doc', id: 'api/b', label: 'B' }, 'missing'] },
+  ]
+  assertEquals(sidebarEntries(sidebar, ids, []), [
+    { relPath: 'docs/intro.md', title: undefined, parents: [] },
+    { relPath: 'docs/guides/setup.md', title: 'Guides', parents: [] },
+    { relPath: 'docs/api/a.md', parents: ['Guides'] },
+    { relPath: 'docs/api/b.md', parents: ['Guides'] },
+    { relPath: 'docs/api/b.md', title: 'B', parents: ['Reference'] },
+  ])
+})